@@ -0,0 +1,413 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error, Result};
+
+/// How loops are drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LoopStyle {
+    /// A loop block that the end of the body returns to with an arrow.
+    #[default]
+    Arrow,
+    /// GOST 19.701 "граница цикла": an opening and a closing boundary block
+    /// with a shared label around the body.
+    Boundary,
+}
+
+impl FromStr for LoopStyle {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "arrow" => Ok(LoopStyle::Arrow),
+            "boundary" => Ok(LoopStyle::Boundary),
+            other => bail!("Unknown loop style `{other}`, expected arrow or boundary"),
+        }
+    }
+}
+
+/// How `for` loops over an integer range are drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CountedLoops {
+    /// A loop block with the range in its header.
+    #[default]
+    Hexagon,
+    /// Counter init, a decision testing it, the body and the increment.
+    Decomposed,
+}
+
+impl FromStr for CountedLoops {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "hexagon" => Ok(CountedLoops::Hexagon),
+            "decomposed" => Ok(CountedLoops::Decomposed),
+            other => bail!("Unknown counted loop style `{other}`, expected hexagon or decomposed"),
+        }
+    }
+}
+
+/// Words written on the outgoing edges of a decision: the branch taken
+/// when the condition holds and the one taken when it does not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchLabels {
+    pub yes: String,
+    pub no: String,
+}
+
+impl Default for BranchLabels {
+    fn default() -> Self {
+        BranchLabels {
+            yes: String::from("да"),
+            no: String::from("нет"),
+        }
+    }
+}
+
+impl FromStr for BranchLabels {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value == "none" {
+            return Ok(BranchLabels {
+                yes: String::new(),
+                no: String::new(),
+            });
+        }
+        match value.split_once('/') {
+            Some((yes, no)) if !yes.is_empty() && !no.is_empty() => Ok(BranchLabels {
+                yes: yes.to_string(),
+                no: no.to_string(),
+            }),
+            _ => bail!("Invalid branch labels `{value}`, expected yes/no (e.g. да/нет, +/-) or none"),
+        }
+    }
+}
+
+/// Paper formats a diagram can be split into pages for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Paper {
+    A4,
+    A3,
+}
+
+/// Diagram units are pixels at 96 dpi.
+pub const PX_PER_MM: f32 = 96.0 / 25.4;
+
+/// Printed page: paper format and orientation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageSize {
+    pub paper: Paper,
+    pub landscape: bool,
+}
+
+impl PageSize {
+    /// Page width and height in diagram units (pixels at 96 dpi).
+    pub fn dimensions(&self) -> (i32, i32) {
+        let (short, long) = match self.paper {
+            Paper::A4 => (794, 1123),
+            Paper::A3 => (1123, 1587),
+        };
+        if self.landscape {
+            (long, short)
+        } else {
+            (short, long)
+        }
+    }
+}
+
+impl FromStr for PageSize {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (paper, orientation) = value.split_once('-').unwrap_or((value, "portrait"));
+        let paper = match paper.to_ascii_lowercase().as_str() {
+            "a4" => Paper::A4,
+            "a3" => Paper::A3,
+            other => bail!("Unknown paper format `{other}`, expected a4 or a3"),
+        };
+        let landscape = match orientation {
+            "portrait" => false,
+            "landscape" => true,
+            other => bail!("Unknown orientation `{other}`, expected portrait or landscape"),
+        };
+        Ok(PageSize { paper, landscape })
+    }
+}
+
+/// File the diagram is written to.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Format {
+    /// programforyou.ru block diagram editor.
+    #[default]
+    Json,
+    /// Standalone vector image.
+    Svg,
+    /// Printable document, one page per function or per `--page` sheet.
+    Pdf,
+    /// Bitmap image.
+    Png,
+    /// Graphviz graph.
+    Dot,
+    /// Mermaid flowchart for Markdown documentation.
+    Mermaid,
+    /// PlantUML activity diagram, written from the block structure.
+    PlantUml,
+    /// Nassi–Shneiderman diagram in SVG, written from the block structure.
+    Structogram,
+    /// DRAKON diagram in SVG, written from the block structure.
+    Drakon,
+    /// LaTeX TikZ picture of the laid out diagram.
+    Tikz,
+    /// GraphML for yEd with the laid out geometry.
+    GraphMl,
+    /// LibreOffice Draw document with glued connectors.
+    Odg,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Svg => "svg",
+            Format::Pdf => "pdf",
+            Format::Png => "png",
+            Format::Dot => "dot",
+            Format::Mermaid => "mmd",
+            Format::PlantUml => "puml",
+            Format::Structogram => "ns.svg",
+            Format::Drakon => "drakon.svg",
+            Format::Tikz => "tex",
+            Format::GraphMl => "graphml",
+            Format::Odg => "odg",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "svg" => Ok(Format::Svg),
+            "pdf" => Ok(Format::Pdf),
+            "png" => Ok(Format::Png),
+            "dot" => Ok(Format::Dot),
+            "mermaid" | "mmd" => Ok(Format::Mermaid),
+            "plantuml" | "puml" => Ok(Format::PlantUml),
+            "structogram" | "nassi" => Ok(Format::Structogram),
+            "drakon" => Ok(Format::Drakon),
+            "tikz" | "tex" => Ok(Format::Tikz),
+            "graphml" | "yed" => Ok(Format::GraphMl),
+            "odg" => Ok(Format::Odg),
+            other => bail!("Unknown output format `{other}`, expected json, svg, pdf, png, dot, mermaid, plantuml, structogram, drakon, tikz, graphml or odg"),
+        }
+    }
+}
+
+/// What is left under the diagram in images.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Background {
+    #[default]
+    White,
+    Transparent,
+}
+
+impl FromStr for Background {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "white" => Ok(Background::White),
+            "transparent" => Ok(Background::Transparent),
+            other => bail!("Unknown background `{other}`, expected white or transparent"),
+        }
+    }
+}
+
+/// Form of a DRAKON diagram.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DrakonForm {
+    /// "Примитив": the whole function on one skewer.
+    #[default]
+    Primitive,
+    /// "Силуэт": the function split into named branches side by side.
+    Silhouette,
+}
+
+impl FromStr for DrakonForm {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "primitive" => Ok(DrakonForm::Primitive),
+            "silhouette" => Ok(DrakonForm::Silhouette),
+            other => bail!("Unknown DRAKON form `{other}`, expected primitive or silhouette"),
+        }
+    }
+}
+
+/// Settings that change how diagrams are built. They are passed on the
+/// command line as `--name` or `--name=value` next to the positional
+/// language and file arguments.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Expand `iter().filter().map().sum()`-style chains into explicit loops.
+    pub expand_iterators: bool,
+    /// Draw `unwrap()`/`expect()` as a decision that can end in a panic.
+    pub unwrap_decisions: bool,
+    /// Draw loops with a back-arrow or as GOST loop-boundary pairs.
+    pub loop_style: LoopStyle,
+    /// Draw `for i in a..b` as a loop block or as init/test/increment.
+    pub counted_loops: CountedLoops,
+    /// Labels on the true/false edges of conditions and `while` loops.
+    pub branch_labels: BranchLabels,
+    /// Split the diagram into pages of this size; one long strip if unset.
+    pub page: Option<PageSize>,
+    /// Draw the `let` declarations a function starts with as one
+    /// preparation block.
+    pub group_declarations: bool,
+    /// Characters per line of text inside a block.
+    pub wrap_width: usize,
+    /// What the diagram is written as.
+    pub format: Format,
+    /// Free border around the drawing on printed pages, in millimetres.
+    pub margin: u32,
+    /// Background of SVG and PNG images.
+    pub background: Background,
+    /// Resolution of PNG images; 96 keeps one pixel per diagram unit.
+    pub dpi: u32,
+    /// Put every function of a DOT graph into its own cluster.
+    pub dot_clusters: bool,
+    /// Wrap a TikZ picture in a `standalone` document that compiles alone.
+    pub tikz_standalone: bool,
+    /// Draw DRAKON functions as primitives or as silhouettes.
+    pub drakon_form: DrakonForm,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            expand_iterators: false,
+            unwrap_decisions: false,
+            loop_style: LoopStyle::default(),
+            counted_loops: CountedLoops::default(),
+            branch_labels: BranchLabels::default(),
+            page: None,
+            group_declarations: false,
+            wrap_width: 24,
+            format: Format::default(),
+            margin: 20,
+            background: Background::default(),
+            dpi: 96,
+            dot_clusters: false,
+            tikz_standalone: false,
+            drakon_form: DrakonForm::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Page margin in diagram units.
+    pub fn margin_px(&self) -> i32 {
+        (self.margin as f32 * PX_PER_MM).round() as i32
+    }
+
+    /// Pulls the settings out of `args` and returns them together with the
+    /// remaining positional arguments.
+    pub fn from_args(args: Vec<String>) -> Result<(Self, Vec<String>)> {
+        let mut config = Config::default();
+        let mut positional = Vec::new();
+
+        for arg in args {
+            let Some(setting) = arg.strip_prefix("--") else {
+                positional.push(arg);
+                continue;
+            };
+            let (name, value) = match setting.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (setting, None),
+            };
+
+            match name {
+                "expand-iterators" => config.expand_iterators = parse_flag(name, value)?,
+                "unwrap-decisions" => config.unwrap_decisions = parse_flag(name, value)?,
+                "loop-style" => config.loop_style = parse_value(name, value)?,
+                "counted-loops" => config.counted_loops = parse_value(name, value)?,
+                "branch-labels" => config.branch_labels = parse_value(name, value)?,
+                "page" => config.page = Some(parse_value(name, value)?),
+                "group-declarations" => config.group_declarations = parse_flag(name, value)?,
+                "wrap-width" => {
+                    config.wrap_width = parse_value(name, value)?;
+                    if config.wrap_width == 0 {
+                        bail!("--wrap-width must be at least 1");
+                    }
+                }
+                "format" => config.format = parse_value(name, value)?,
+                "margin" => config.margin = parse_value(name, value)?,
+                "dot-clusters" => config.dot_clusters = parse_flag(name, value)?,
+                "tikz-standalone" => config.tikz_standalone = parse_flag(name, value)?,
+                "drakon-form" => config.drakon_form = parse_value(name, value)?,
+                "background" => config.background = parse_value(name, value)?,
+                "dpi" => {
+                    config.dpi = parse_value(name, value)?;
+                    if config.dpi == 0 {
+                        bail!("--dpi must be at least 1");
+                    }
+                }
+                _ => bail!("Unknown option `--{name}`"),
+            }
+        }
+
+        Ok((config, positional))
+    }
+}
+
+fn parse_flag(name: &str, value: Option<&str>) -> Result<bool> {
+    match value {
+        None | Some("true" | "yes" | "on") => Ok(true),
+        Some("false" | "no" | "off") => Ok(false),
+        Some(other) => bail!("Invalid value `{other}` for --{name}, expected true or false"),
+    }
+}
+
+fn parse_value<T: FromStr>(name: &str, value: Option<&str>) -> Result<T>
+where
+    Error: From<T::Err>,
+{
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(Error::from)
+            .with_context(|| format!("Invalid value `{value}` for --{name}")),
+        None => bail!("--{name} needs a value, e.g. --{name}=<value>"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn options_are_taken_out_of_the_arguments() {
+        let (config, positional) =
+            Config::from_args(args(&["prog", "rust", "--wrap-width=30", "main.rs", "--format=svg"])).unwrap();
+        assert_eq!(config.wrap_width, 30);
+        assert_eq!(config.format, Format::Svg);
+        assert_eq!(positional, ["prog", "rust", "main.rs"]);
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        for typo in ["--wrap_width=30", "--fromat=svg", "--clusters"] {
+            let error = Config::from_args(args(&["prog", "rust", typo])).unwrap_err();
+            let name = typo.split('=').next().unwrap();
+            assert_eq!(error.to_string(), format!("Unknown option `{name}`"));
+        }
+    }
+}
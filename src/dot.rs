@@ -0,0 +1,140 @@
+use crate::config::Config;
+use crate::mk_json_blocks::{function_numbers, FullJson, JsBlock};
+
+/// Writes a diagram as a Graphviz graph for `dot` to lay out. Loop return
+/// arrows do not constrain the ranks, so the loop body still flows down.
+pub fn render_dot(diagram: &FullJson, config: &Config) -> String {
+    let mut dot = String::from(
+        "digraph flowchart {\n    \
+         node [fontname=\"Arial\", fontsize=10];\n    \
+         edge [fontname=\"Arial\", fontsize=9];\n",
+    );
+
+    if config.dot_clusters {
+        let functions = function_numbers(diagram);
+        let count = functions.iter().max().map_or(0, |last| last + 1);
+        for function in 0..count {
+            let mut members = (0..diagram.blocks.len()).filter(|&index| functions[index] == function);
+            let Some(first) = members.next() else {
+                continue;
+            };
+            let start = &diagram.blocks[first];
+            dot.push_str(&format!(
+                "    subgraph cluster_{function} {{\n        label=\"{}\";\n",
+                escape(start.link.as_deref().unwrap_or(&start.full_text))
+            ));
+            let members = std::iter::once(first).chain(members).filter(|&i| !diagram.blocks[i].repeats_caption());
+            for index in members {
+                dot.push_str(&format!("        {}\n", node(index, &diagram.blocks[index])));
+            }
+            dot.push_str("    }\n");
+        }
+    } else {
+        for (index, block) in diagram.blocks.iter().enumerate().filter(|(_, b)| !b.repeats_caption()) {
+            dot.push_str(&format!("    {}\n", node(index, block)));
+        }
+    }
+
+    let arrows = diagram.arrows.iter()
+        .filter(|a| !diagram.blocks.get(a.end_index).is_some_and(JsBlock::repeats_caption));
+    for arrow in arrows {
+        let mut attributes = Vec::new();
+        if !arrow.label.is_empty() {
+            attributes.push(format!("label=\"{}\"", escape(&arrow.label)));
+        }
+        if arrow.start_connector_index != 2 {
+            attributes.push(format!("tailport={}", port(arrow.start_connector_index)));
+        }
+        if arrow.end_connector_index != 0 {
+            attributes.push(format!("headport={}", port(arrow.end_connector_index)));
+        }
+        if arrow.dashed {
+            attributes.push(String::from("style=dashed, arrowhead=none"));
+        } else if arrow.end_index <= arrow.start_index {
+            attributes.push(String::from("constraint=false"));
+        }
+        let attributes = if attributes.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", attributes.join(", "))
+        };
+        dot.push_str(&format!("    b{} -> b{}{attributes};\n", arrow.start_index, arrow.end_index));
+        if arrow.dashed {
+            // комментарий стоит справа от своего блока
+            dot.push_str(&format!("    {{ rank=same; b{}; b{}; }}\n", arrow.start_index, arrow.end_index));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn node(index: usize, block: &JsBlock) -> String {
+    let shape = match block.r#type.as_str() {
+        "Условие" => "diamond",
+        "Ввод / вывод" => "parallelogram",
+        "Начало / конец" => "ellipse",
+        "Цикл for" | "Подготовка" => "hexagon",
+        "Предопределённый процесс" => "box, peripheries=2",
+        "Начало цикла" => "trapezium",
+        "Конец цикла" => "invtrapezium",
+        "Соединитель" => "circle",
+        "Межстраничный соединитель" => "invhouse",
+        "Комментарий" => "note",
+        _ => "box",
+    };
+    let label = if block.text_align == "left" {
+        // \l завершает строку, выровненную по левому краю
+        block.full_text.lines().map(|line| format!("{}\\l", escape(line))).collect()
+    } else {
+        escape(&block.full_text)
+    };
+    format!("b{index} [shape={shape}, label=\"{label}\"];")
+}
+
+/// Compass point of a programforyou connector.
+fn port(connector: usize) -> &'static str {
+    match connector {
+        0 => "n",
+        1 => "e",
+        2 => "s",
+        _ => "w",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang_vec_stuf::{Language, Rust};
+    use crate::mk_json_blocks::build_diagram;
+    use crate::sizing::fit_blocks;
+
+    #[test]
+    fn captions_are_written_in_full() {
+        let statement = "let answer = compute(total, values.len(), \"a fairly long string literal\")";
+        let source = format!("fn helper() {{\n    {statement};\n}}\n");
+        let config = Config { dot_clusters: true, ..Config::default() };
+        let mut blocks = Rust.analyze_to_vec(source, &config);
+        fit_blocks(&mut blocks, config.wrap_width);
+        let dot = render_dot(&build_diagram(blocks, &config), &config);
+        assert!(dot.contains(&format!("label=\"{}\"", escape(statement))), "{dot}");
+        assert!(dot.contains("label=\"helper\";"));
+        // комментарий с полным текстом в графе не нужен
+        assert!(!dot.contains("shape=note"), "{dot}");
+    }
+
+    #[test]
+    fn comment_lines_are_left_aligned() {
+        let source = "fn f() {\n    // печать \"a\\n\"\n    // и выход\n    go();\n}\n";
+        let config = Config::default();
+        let mut blocks = Rust.analyze_to_vec(source.to_string(), &config);
+        fit_blocks(&mut blocks, config.wrap_width);
+        let dot = render_dot(&build_diagram(blocks, &config), &config);
+        assert!(dot.contains("label=\"печать \\\"a\\\\n\\\"\\lи выход\\l\""), "{dot}");
+    }
+}
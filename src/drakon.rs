@@ -0,0 +1,677 @@
+use crate::config::{Background, BranchLabels, Config, DrakonForm};
+use crate::lang_vec_stuf::{BlockType, CodeBlock};
+use crate::sizing::{text_size, FONT_SIZE, LINE_HEIGHT, PADDING};
+use crate::structure::{diverges, functions, header_text, Function, Statement};
+use crate::svg::{escape, FONT_FAMILY};
+use crate::wrap::caption_text;
+
+/// Free space around the drawing.
+const MARGIN: f32 = 20.0;
+/// Length of the skewer between two icons.
+const STEP: f32 = 20.0;
+/// Free space between a column and the branch to its right.
+const BRANCH_GAP: f32 = 40.0;
+/// Free space between the diagrams of two functions.
+const FUNCTION_GAP: f32 = 80.0;
+const MIN_WIDTH: f32 = 100.0;
+const MIN_HEIGHT: f32 = 40.0;
+const END: &str = "Конец";
+/// Name of the silhouette branch before the first commented statement.
+const FIRST_BRANCH: &str = "Начало";
+
+/// Draws every function as a DRAKON diagram, side by side: icons hang on
+/// a vertical skewer from the headline to the end icon, a question goes
+/// on down for "yes" and to the right for "no" (the further right, the
+/// worse the path), choice variants and parallel processes spread to the
+/// right and join back on the skewer below. Early exits run down the right
+/// edge to the single end icon. The "силуэт" form splits a function into
+/// branches at its commented top-level statements.
+pub fn render_drakon(blocks: &[CodeBlock], config: &Config) -> String {
+    let layout = Layout {
+        wrap_width: config.wrap_width,
+        labels: &config.branch_labels,
+        form: config.drakon_form,
+    };
+    let mut canvas = Canvas::default();
+    let mut right = MARGIN - FUNCTION_GAP;
+    let mut bottom = 0.0_f32;
+    for function in functions(blocks) {
+        let (width, height) = layout.draw_function(&function, right + FUNCTION_GAP, MARGIN, &mut canvas);
+        right += FUNCTION_GAP + width;
+        bottom = bottom.max(MARGIN + height);
+    }
+    let (width, height) = ((right + MARGIN).ceil(), (bottom + MARGIN).ceil());
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" font-family=\"{FONT_FAMILY}\" font-size=\"{FONT_SIZE}\">\n"
+    );
+    if config.background == Background::White {
+        svg.push_str(&format!("<rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>\n"));
+    }
+    svg.push_str("<g fill=\"none\" stroke=\"black\" stroke-width=\"1.5\">\n");
+    svg.push_str(&canvas.lines);
+    svg.push_str("</g>\n<g fill=\"white\" stroke=\"black\" stroke-width=\"1.5\">\n");
+    svg.push_str(&canvas.icons);
+    svg.push_str("</g>\n<g fill=\"black\">\n");
+    svg.push_str(&canvas.texts);
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+/// Lines go under the icons, so the skewer may run straight through them.
+#[derive(Default)]
+struct Canvas {
+    lines: String,
+    icons: String,
+    texts: String,
+}
+
+impl Canvas {
+    fn line(&mut self, points: &[(f32, f32)]) {
+        let points = points.iter()
+            .map(|(x, y)| format!("{x},{y}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.lines.push_str(&format!("<polyline points=\"{points}\"/>\n"));
+    }
+
+    fn text(&mut self, lines: &str, x: f32, y: f32, anchor: &str) {
+        let count = lines.lines().count().max(1);
+        let first = y - (count - 1) as f32 * LINE_HEIGHT / 2.0 + 0.35 * FONT_SIZE as f32;
+        for (index, line) in lines.lines().enumerate() {
+            self.texts.push_str(&format!(
+                "<text x=\"{x}\" y=\"{}\" text-anchor=\"{anchor}\">{}</text>\n",
+                first + index as f32 * LINE_HEIGHT,
+                escape(line)
+            ));
+        }
+    }
+
+    /// Draws an icon centred on the skewer `x` with its top at `y`.
+    fn icon(&mut self, shape: Shape, x: f32, y: f32, icon: &Icon) {
+        let (left, right, bottom) = (x - icon.width / 2.0, x + icon.width / 2.0, y + icon.height);
+        let middle = y + icon.height / 2.0;
+        let d = icon.height / 4.0;
+        let points = match shape {
+            Shape::Capsule => {
+                self.icons.push_str(&format!(
+                    "<rect x=\"{left}\" y=\"{y}\" width=\"{}\" height=\"{}\" rx=\"{}\"/>\n",
+                    icon.width,
+                    icon.height,
+                    icon.height / 2.0
+                ));
+                Vec::new()
+            }
+            Shape::Action | Shape::Insertion => vec![(left, y), (right, y), (right, bottom), (left, bottom)],
+            Shape::Question => vec![
+                (left + d, y), (right - d, y), (right, middle), (right - d, bottom), (left + d, bottom), (left, middle),
+            ],
+            Shape::Choice => vec![(left + d, y), (right, y), (right - d, bottom), (left, bottom)],
+            Shape::Case => vec![(left + d, y), (right, y), (right, bottom), (left, bottom), (left, y + d)],
+            Shape::LoopStart => vec![(left + d, y), (right - d, y), (right, y + d), (right, bottom), (left, bottom), (left, y + d)],
+            Shape::LoopEnd => vec![(left, y), (right, y), (right, bottom - d), (right - d, bottom), (left + d, bottom), (left, bottom - d)],
+            Shape::BranchName => vec![(left, y + d), (x, y), (right, y + d), (right, bottom), (left, bottom)],
+            Shape::Address => vec![(left, y), (right, y), (right, bottom - d), (x, bottom), (left, bottom - d)],
+            Shape::Output => vec![(left, y), (right - d, y), (right, middle), (right - d, bottom), (left, bottom)],
+            Shape::Input => vec![(left, y), (right, y), (right, bottom), (left, bottom), (left + d, middle)],
+        };
+        if !points.is_empty() {
+            let points = points.iter()
+                .map(|(x, y)| format!("{x},{y}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            self.icons.push_str(&format!("<polygon points=\"{points}\"/>\n"));
+        }
+        if shape == Shape::Insertion {
+            for side in [left + d / 2.0, right - d / 2.0] {
+                self.icons.push_str(&format!("<polyline points=\"{side},{y} {side},{bottom}\"/>\n"));
+            }
+        }
+        self.text(&icon.lines, x, middle, "middle");
+    }
+}
+
+/// DRAKON icons.
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    /// Headline and end.
+    Capsule,
+    Action,
+    /// Call of another function ("вставка").
+    Insertion,
+    Question,
+    Choice,
+    /// Variant of a choice.
+    Case,
+    LoopStart,
+    LoopEnd,
+    Output,
+    Input,
+    /// First icon of a silhouette branch.
+    BranchName,
+    /// Last icon of a silhouette branch, naming the branch that follows.
+    Address,
+}
+
+/// Wrapped caption with the size of its icon.
+struct Icon {
+    lines: String,
+    width: f32,
+    height: f32,
+}
+
+/// Space a statement takes: to the left and to the right of its skewer,
+/// and down to the top of the next icon.
+#[derive(Clone, Copy)]
+struct Extent {
+    left: f32,
+    right: f32,
+    height: f32,
+}
+
+/// Bottom of a `return` or exit icon, where its line to the end starts.
+#[derive(Clone, Copy)]
+struct Exit {
+    x: f32,
+    y: f32,
+    /// Already led down below the columns around it, free to turn right.
+    routed: bool,
+}
+
+/// Column of a silhouette: a named part of the function body. The
+/// primitive form draws the whole body as one unnamed branch.
+struct Branch<'s, 'a> {
+    name: Option<&'a str>,
+    body: &'s [Statement<'a>],
+}
+
+struct Layout<'a> {
+    wrap_width: usize,
+    labels: &'a BranchLabels,
+    form: DrakonForm,
+}
+
+impl Layout<'_> {
+    fn icon(&self, text: &str) -> Icon {
+        let lines = caption_text(text, self.wrap_width);
+        let (width, height) = text_size(&lines);
+        let height = (height + 2.0 * PADDING).max(MIN_HEIGHT);
+        Icon {
+            lines,
+            width: (width + 2.0 * PADDING + height / 2.0).max(MIN_WIDTH),
+            height,
+        }
+    }
+
+    fn headline(&self, function: &Function) -> Icon {
+        let name = function.start.map_or("", |start| start.link.as_deref().unwrap_or(&start.text));
+        self.icon(name)
+    }
+
+    /// A silhouette branch starts at every top-level statement with a
+    /// comment, whose first line names the branch.
+    fn branches<'s, 'a>(&self, function: &'s Function<'a>) -> Vec<Branch<'s, 'a>> {
+        let body = function.body.as_slice();
+        if self.form == DrakonForm::Primitive {
+            return vec![Branch { name: None, body }];
+        }
+        let mut starts: Vec<usize> = (1..body.len()).filter(|&i| head(&body[i]).comment.is_some()).collect();
+        starts.insert(0, 0);
+        starts.iter()
+            .enumerate()
+            .map(|(index, &start)| {
+                let end = starts.get(index + 1).copied().unwrap_or(body.len());
+                let name = body.get(start)
+                    .and_then(|statement| head(statement).comment.as_deref())
+                    .and_then(|comment| comment.lines().next())
+                    .unwrap_or(FIRST_BRANCH);
+                Branch { name: Some(name), body: &body[start..end] }
+            })
+            .collect()
+    }
+
+    /// Draws a function from its headline down to the end icon with its
+    /// left edge at `left`; returns the width and the height.
+    fn draw_function(&self, function: &Function, left: f32, y: f32, canvas: &mut Canvas) -> (f32, f32) {
+        let headline = self.headline(function);
+        let branches = self.branches(function);
+        let end = self.icon(END);
+        // a branch ends in the end icon or in the address of the next one
+        let last_icons: Vec<Icon> = (1..=branches.len())
+            .map(|next| branches.get(next).and_then(|b| b.name).map_or_else(|| self.icon(END), |name| self.icon(name)))
+            .collect();
+
+        let (mut skewers, mut lanes) = (Vec::new(), Vec::new());
+        let mut right = left;
+        for (index, branch) in branches.iter().enumerate() {
+            let is_last = index + 1 == branches.len();
+            let body = self.sequence(branch.body);
+            let mut half = last_icons[index].width.max(branch.name.map_or(0.0, |name| self.icon(name).width)) / 2.0;
+            if index == 0 {
+                half = half.max(headline.width / 2.0);
+            }
+            let skewer = right + if index == 0 { 0.0 } else { BRANCH_GAP } + body.left.max(half);
+            right = skewer + body.right.max(half);
+            let lane = has_lane(branch.body, is_last).then(|| {
+                let lane = right + BRANCH_GAP / 2.0;
+                // кроме последней ветки, выход ведёт к адресу «Конец»
+                right = if is_last { lane } else { lane + end.width / 2.0 };
+                lane
+            });
+            skewers.push(skewer);
+            lanes.push(lane);
+        }
+
+        canvas.icon(Shape::Capsule, skewers[0], y, &headline);
+        let mut top = y + headline.height + STEP;
+        canvas.line(&[(skewers[0], y + headline.height), (skewers[0], top)]);
+        if self.form == DrakonForm::Silhouette {
+            let last = skewers[skewers.len() - 1];
+            canvas.line(&[(skewers[0], top), (last, top)]);
+            let names: Vec<Icon> = branches.iter().map(|b| self.icon(b.name.unwrap_or_default())).collect();
+            let height = names.iter().map(|icon| icon.height).fold(0.0, f32::max);
+            for (icon, &x) in names.iter().zip(&skewers) {
+                canvas.line(&[(x, top), (x, top + STEP)]);
+                canvas.icon(Shape::BranchName, x, top + STEP, icon);
+                canvas.line(&[(x, top + STEP + icon.height), (x, top + 2.0 * STEP + height)]);
+            }
+            top += 2.0 * STEP + height;
+        }
+        let bottom = top + branches.iter().map(|b| self.sequence(b.body).height).fold(0.0, f32::max);
+
+        for (index, branch) in branches.iter().enumerate() {
+            let (x, is_last) = (skewers[index], index + 1 == branches.len());
+            let mut exits = Vec::new();
+            self.draw_sequence(branch.body, x, top, canvas, &mut exits);
+            let body_bottom = top + self.sequence(branch.body).height;
+            if !diverges(branch.body) {
+                canvas.line(&[(x, body_bottom), (x, bottom)]);
+            }
+            if is_last {
+                canvas.icon(Shape::Capsule, x, bottom, &end);
+            } else if !diverges(branch.body) {
+                canvas.icon(Shape::Address, x, bottom, &last_icons[index]);
+            }
+            for exit in exits {
+                // a `return` ending the function leads straight down to its end
+                let straight = is_last && !exit.routed && exit.y + STEP >= body_bottom;
+                let Some(lane) = lanes[index].filter(|_| !straight) else {
+                    canvas.line(&[(exit.x, exit.y), (exit.x, bottom)]);
+                    continue;
+                };
+                let turn = if exit.routed { exit.y } else { exit.y + STEP / 2.0 };
+                if is_last && bottom - turn <= 2.0 * STEP {
+                    // под последней развилкой больше ничего нет, конец прямо под ней
+                    canvas.line(&[(exit.x, exit.y), (exit.x, turn), (x, turn), (x, bottom)]);
+                    continue;
+                }
+                let mut line = vec![(exit.x, exit.y), (exit.x, turn), (lane, turn)];
+                if is_last {
+                    line.extend([(lane, bottom - STEP / 2.0), (x, bottom - STEP / 2.0), (x, bottom)]);
+                } else {
+                    line.push((lane, bottom));
+                }
+                canvas.line(&line);
+            }
+            if let Some(lane) = lanes[index].filter(|_| !is_last) {
+                canvas.icon(Shape::Address, lane, bottom, &end);
+            }
+        }
+        let height = last_icons.iter().map(|icon| icon.height).fold(0.0, f32::max);
+        (right - left, bottom + height - y)
+    }
+
+    fn sequence(&self, statements: &[Statement]) -> Extent {
+        if statements.is_empty() {
+            return Extent { left: 0.0, right: 0.0, height: STEP };
+        }
+        statements.iter()
+            .map(|s| self.extent(s))
+            .fold(Extent { left: 0.0, right: 0.0, height: 0.0 }, |sum, e| Extent {
+                left: sum.left.max(e.left),
+                right: sum.right.max(e.right),
+                height: sum.height + e.height,
+            })
+    }
+
+    fn draw_sequence(&self, statements: &[Statement], x: f32, y: f32, canvas: &mut Canvas, exits: &mut Vec<Exit>) {
+        if statements.is_empty() {
+            canvas.line(&[(x, y), (x, y + STEP)]);
+        }
+        let mut top = y;
+        for statement in statements {
+            self.draw(statement, x, top, canvas, exits);
+            top += self.extent(statement).height;
+        }
+    }
+
+    /// Left column and branches of a question, choice or parallel block:
+    /// skewer offsets of the columns and the height of the tallest one.
+    fn columns(&self, columns: &[Extent]) -> (Vec<f32>, f32) {
+        let mut offsets = Vec::with_capacity(columns.len());
+        let mut offset = 0.0;
+        for (index, column) in columns.iter().enumerate() {
+            if index > 0 {
+                offset += columns[index - 1].right + BRANCH_GAP + column.left;
+            }
+            offsets.push(offset);
+        }
+        (offsets, columns.iter().map(|c| c.height).fold(0.0, f32::max))
+    }
+
+    fn case_column(&self, label: &str, body: &[Statement]) -> Extent {
+        let icon = self.icon(label);
+        let body = self.sequence(body);
+        Extent {
+            left: body.left.max(icon.width / 2.0),
+            right: body.right.max(icon.width / 2.0),
+            height: icon.height + STEP + body.height,
+        }
+    }
+
+    fn extent(&self, statement: &Statement) -> Extent {
+        match statement {
+            Statement::Simple(block) => {
+                let icon = self.icon(block.full_caption());
+                Extent { left: icon.width / 2.0, right: icon.width / 2.0, height: icon.height + STEP }
+            }
+            Statement::If { block, then, otherwise } => {
+                let question = self.icon(header_text(block));
+                let (down, side, _) = question_columns(then, otherwise.as_deref().unwrap_or_default());
+                let band = exit_band([down, side]);
+                let (down, side) = (self.sequence(down), self.sequence(side));
+                let half = question.width / 2.0;
+                let down = Extent { right: down.right.max(half), ..down };
+                let (offsets, height) = self.columns(&[down, side]);
+                Extent {
+                    left: down.left.max(half),
+                    right: offsets[1] + side.right,
+                    height: question.height + STEP + height + band + STEP,
+                }
+            }
+            Statement::Match { block, arms } => {
+                let choice = self.icon(header_text(block));
+                let columns: Vec<Extent> = arms.iter().map(|arm| self.case_column(arm.label, &arm.body)).collect();
+                self.spread(&choice, &columns, exit_band(arms.iter().map(|arm| arm.body.as_slice())))
+            }
+            Statement::Parallel { block, arms } => {
+                let title = self.icon(block.full_caption());
+                let columns: Vec<Extent> = arms.iter().map(|arm| self.sequence(arm)).collect();
+                self.spread(&title, &columns, exit_band(arms.iter().map(Vec::as_slice)))
+            }
+            Statement::Loop { block, header, step, body } => {
+                let start = self.icon(header.unwrap_or(block.full_caption()));
+                let end = self.icon("");
+                let body = self.sequence(body);
+                let step = step.map(|step| self.icon(step));
+                let half = start.width.max(step.as_ref().map_or(0.0, |s| s.width)) / 2.0;
+                Extent {
+                    left: body.left.max(half),
+                    right: body.right.max(half),
+                    height: start.height + STEP + body.height + step.map_or(0.0, |s| s.height + STEP) + end.height + STEP,
+                }
+            }
+        }
+    }
+
+    /// Choice or parallel block: the icon on the skewer, a bus line with the
+    /// columns below it and their join.
+    fn spread(&self, icon: &Icon, columns: &[Extent], band: f32) -> Extent {
+        let (offsets, height) = self.columns(columns);
+        let last = offsets.last().copied().unwrap_or_default() + columns.last().map_or(0.0, |c| c.right);
+        Extent {
+            left: columns.first().map_or(0.0, |c| c.left).max(icon.width / 2.0),
+            right: last.max(icon.width / 2.0),
+            height: icon.height + 2.0 * STEP + height + band + STEP,
+        }
+    }
+
+    fn draw(&self, statement: &Statement, x: f32, y: f32, canvas: &mut Canvas, exits: &mut Vec<Exit>) {
+        match statement {
+            Statement::Simple(block) => {
+                let icon = self.icon(block.full_caption());
+                canvas.icon(shape(block), x, y, &icon);
+                if statement.stops() {
+                    exits.push(Exit { x, y: y + icon.height, routed: false });
+                } else {
+                    canvas.line(&[(x, y + icon.height), (x, y + icon.height + STEP)]);
+                }
+            }
+            Statement::If { block, then, otherwise } => {
+                let question = self.icon(header_text(block));
+                let (down, side, swapped) = question_columns(then, otherwise.as_deref().unwrap_or_default());
+                let (down_label, side_label) = if swapped {
+                    (&self.labels.no, &self.labels.yes)
+                } else {
+                    (&self.labels.yes, &self.labels.no)
+                };
+                let Extent { right, .. } = self.extent(statement);
+                let down_extent = self.sequence(down);
+                let side_extent = self.sequence(side);
+                let branch = x + right - side_extent.right;
+                let middle = y + question.height / 2.0;
+                let top = y + question.height + STEP;
+                let floor = top + down_extent.height.max(side_extent.height);
+                let band = exit_band([down, side]);
+                let join = floor + band;
+
+                canvas.icon(Shape::Question, x, y, &question);
+                canvas.line(&[(x, y + question.height), (x, top)]);
+                canvas.line(&[(x + question.width / 2.0, middle), (branch, middle), (branch, top)]);
+                canvas.text(down_label, x + 4.0, y + question.height + STEP / 2.0, "start");
+                canvas.text(side_label, x + question.width / 2.0 + 4.0, middle - LINE_HEIGHT / 2.0, "start");
+
+                let mut inner = Vec::new();
+                self.draw_sequence(down, x, top, canvas, &mut inner);
+                if !diverges(down) {
+                    canvas.line(&[(x, top + down_extent.height), (x, join)]);
+                }
+                self.draw_sequence(side, branch, top, canvas, &mut inner);
+                if !diverges(side) {
+                    canvas.line(&[(branch, top + side_extent.height), (branch, join), (x, join)]);
+                }
+                if !statement.stops() {
+                    canvas.line(&[(x, join), (x, join + STEP)]);
+                }
+                route(&mut inner, floor + band / 2.0, canvas);
+                exits.append(&mut inner);
+            }
+            Statement::Match { block, arms } => {
+                let choice = self.icon(header_text(block));
+                canvas.icon(Shape::Choice, x, y, &choice);
+                let columns: Vec<Extent> = arms.iter().map(|arm| self.case_column(arm.label, &arm.body)).collect();
+                let (offsets, height) = self.columns(&columns);
+                let bus = y + choice.height + STEP;
+                let floor = bus + STEP + height;
+                let band = exit_band(arms.iter().map(|arm| arm.body.as_slice()));
+                let join = floor + band;
+                canvas.line(&[(x, y + choice.height), (x, bus), (x + offsets.last().copied().unwrap_or_default(), bus)]);
+                let mut inner = Vec::new();
+                for ((arm, column), offset) in arms.iter().zip(&columns).zip(&offsets) {
+                    let column_x = x + offset;
+                    let case = self.icon(arm.label);
+                    canvas.line(&[(column_x, bus), (column_x, bus + STEP)]);
+                    canvas.icon(Shape::Case, column_x, bus + STEP, &case);
+                    let top = bus + STEP + case.height;
+                    canvas.line(&[(column_x, top), (column_x, top + STEP)]);
+                    self.draw_sequence(&arm.body, column_x, top + STEP, canvas, &mut inner);
+                    if !diverges(&arm.body) {
+                        canvas.line(&[(column_x, bus + STEP + column.height), (column_x, join), (x, join)]);
+                    }
+                }
+                if !statement.stops() {
+                    canvas.line(&[(x, join), (x, join + STEP)]);
+                }
+                route(&mut inner, floor + band / 2.0, canvas);
+                exits.append(&mut inner);
+            }
+            Statement::Parallel { block, arms } => {
+                let title = self.icon(block.full_caption());
+                canvas.icon(Shape::Action, x, y, &title);
+                let columns: Vec<Extent> = arms.iter().map(|arm| self.sequence(arm)).collect();
+                let (offsets, height) = self.columns(&columns);
+                let bus = y + title.height + STEP;
+                let floor = bus + STEP + height;
+                let band = exit_band(arms.iter().map(Vec::as_slice));
+                let join = floor + band;
+                let (left, right) = (x - STEP, x + offsets.last().copied().unwrap_or_default() + STEP);
+                canvas.line(&[(x, y + title.height), (x, bus)]);
+                // параллельные процессы начинаются и сходятся на двойной линии
+                for level in [bus, bus + 4.0, join, join + 4.0] {
+                    canvas.line(&[(left, level), (right, level)]);
+                }
+                let mut inner = Vec::new();
+                for ((arm, column), offset) in arms.iter().zip(&columns).zip(&offsets) {
+                    let column_x = x + offset;
+                    canvas.line(&[(column_x, bus + 4.0), (column_x, bus + STEP)]);
+                    self.draw_sequence(arm, column_x, bus + STEP, canvas, &mut inner);
+                    if !diverges(arm) {
+                        canvas.line(&[(column_x, bus + STEP + column.height), (column_x, join)]);
+                    }
+                }
+                canvas.line(&[(x, join + 4.0), (x, join + STEP)]);
+                route(&mut inner, floor + band / 2.0, canvas);
+                exits.append(&mut inner);
+            }
+            Statement::Loop { block, header, step, body } => {
+                let start = self.icon(header.unwrap_or(block.full_caption()));
+                let end = self.icon("");
+                canvas.icon(Shape::LoopStart, x, y, &start);
+                let mut top = y + start.height;
+                canvas.line(&[(x, top), (x, top + STEP)]);
+                top += STEP;
+                let mut inner = Vec::new();
+                self.draw_sequence(body, x, top, canvas, &mut inner);
+                // выход из тела цикла сворачивает вправо сразу под своей иконкой
+                for exit in inner.iter_mut().filter(|exit| !exit.routed) {
+                    let turn = exit.y + STEP / 2.0;
+                    canvas.line(&[(exit.x, exit.y), (exit.x, turn)]);
+                    *exit = Exit { y: turn, routed: true, ..*exit };
+                }
+                exits.append(&mut inner);
+                top += self.sequence(body).height;
+                if let Some(step) = step {
+                    let icon = self.icon(step);
+                    canvas.icon(Shape::Action, x, top, &icon);
+                    canvas.line(&[(x, top + icon.height), (x, top + icon.height + STEP)]);
+                    top += icon.height + STEP;
+                }
+                canvas.icon(Shape::LoopEnd, x, top, &end);
+                canvas.line(&[(x, top + end.height), (x, top + end.height + STEP)]);
+            }
+        }
+    }
+}
+
+/// Columns of a question: the one going on down the skewer and the one to
+/// its right, and whether "yes" is the right one. A branch that ends the
+/// function goes right, so the skewer keeps the main path.
+fn question_columns<'s, 'a>(
+    then: &'s [Statement<'a>],
+    otherwise: &'s [Statement<'a>],
+) -> (&'s [Statement<'a>], &'s [Statement<'a>], bool) {
+    if diverges(then) && !diverges(otherwise) {
+        (otherwise, then, true)
+    } else {
+        (then, otherwise, false)
+    }
+}
+
+/// Room under the columns of a branching for the lines of the exits they
+/// end with, which turn right there towards the end icon.
+fn exit_band<'s, 'a: 's>(columns: impl IntoIterator<Item = &'s [Statement<'a>]>) -> f32 {
+    let ends_with_exit = |column: &[Statement]| matches!(column.last(), Some(last @ Statement::Simple(_)) if last.stops());
+    if columns.into_iter().any(ends_with_exit) {
+        STEP
+    } else {
+        0.0
+    }
+}
+
+/// Leads the exits the columns of a branching end with down to `y`.
+fn route(exits: &mut [Exit], y: f32, canvas: &mut Canvas) {
+    for exit in exits.iter_mut().filter(|exit| !exit.routed) {
+        canvas.line(&[(exit.x, exit.y), (exit.x, y)]);
+        *exit = Exit { y, routed: true, ..*exit };
+    }
+}
+
+/// Whether the exits of a branch need a lane on its right: every exit
+/// but a `return` ending the body of the last branch, which goes straight
+/// down to the end icon.
+fn has_lane(body: &[Statement], is_last: bool) -> bool {
+    let Some((last, rest)) = body.split_last() else {
+        return false;
+    };
+    let last_exits = match last {
+        Statement::Simple(_) => !is_last && last.stops(),
+        _ => has_exit(std::slice::from_ref(last)),
+    };
+    last_exits || has_exit(rest)
+}
+
+fn has_exit(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Simple(_) => statement.stops(),
+        Statement::If { then, otherwise, .. } => has_exit(then) || otherwise.as_deref().is_some_and(has_exit),
+        Statement::Match { arms, .. } => arms.iter().any(|arm| has_exit(&arm.body)),
+        Statement::Parallel { arms, .. } => arms.iter().any(|arm| has_exit(arm)),
+        Statement::Loop { body, .. } => has_exit(body),
+    })
+}
+
+/// Block a statement starts with, the one its comment belongs to.
+fn head<'a>(statement: &Statement<'a>) -> &'a CodeBlock {
+    match statement {
+        Statement::Simple(block)
+        | Statement::If { block, .. }
+        | Statement::Match { block, .. }
+        | Statement::Parallel { block, .. }
+        | Statement::Loop { block, .. } => block,
+    }
+}
+
+fn shape(block: &CodeBlock) -> Shape {
+    match block.r#type {
+        BlockType::Print => Shape::Output,
+        BlockType::Input => Shape::Input,
+        BlockType::Subroutine => Shape::Insertion,
+        _ => Shape::Action,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang_vec_stuf::{Language, Rust};
+
+    const SOURCE: &str = "fn sign(x: i32) -> i32 {\n    if x < 0 {\n        return -1;\n    }\n    \
+                          for i in 0..x {\n        if i == 7 {\n            panic!(\"seven\");\n        }\n    }\n    1\n}\n\n\
+                          fn main() {\n    // ввод\n    let n = read();\n    // вывод\n    println!(\"{}\", sign(n));\n}\n";
+
+    fn drakon(form: DrakonForm) -> String {
+        let config = Config { drakon_form: form, ..Config::default() };
+        render_drakon(&Rust.analyze_to_vec(SOURCE.to_string(), &config), &config)
+    }
+
+    #[test]
+    fn every_function_has_one_end() {
+        let svg = drakon(DrakonForm::Primitive);
+        // капсулы — только заголовок и конец каждой функции
+        assert_eq!(svg.matches(" rx=").count(), 4, "{svg}");
+        assert_eq!(svg.matches(">Конец<").count(), 2);
+        assert!(svg.contains(">return -1<") && svg.contains(">panic!(&quot;seven&quot;)<"), "{svg}");
+    }
+
+    #[test]
+    fn silhouette_splits_at_commented_statements() {
+        let svg = drakon(DrakonForm::Silhouette);
+        for name in ["Начало", "ввод", "вывод"] {
+            assert!(svg.contains(&format!(">{name}<")), "{name}: {svg}");
+        }
+        // ветка «ввод» кончается адресом следующей ветки
+        assert_eq!(svg.matches(">вывод<").count(), 2);
+        assert_eq!(svg.matches(" rx=").count(), 4);
+    }
+}
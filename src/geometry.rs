@@ -0,0 +1,275 @@
+use std::f32::consts::FRAC_PI_2;
+
+use crate::mk_json_blocks::{Arrow, FullJson, JsBlock, Node};
+use crate::sizing::{FONT_SIZE, GAP, LINE_HEIGHT, PADDING};
+
+/// Segments approximating a quarter of a circle.
+const ARC_STEPS: usize = 8;
+
+/// One line of a block outline, closed into a polygon or left open.
+pub(crate) struct Figure {
+    pub(crate) points: Vec<(f32, f32)>,
+    pub(crate) closed: bool,
+}
+
+/// Which end of a line of text sits at its anchor point.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// Line of text placed with its baseline at `y`.
+pub(crate) struct TextLine<'a> {
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    pub(crate) anchor: Anchor,
+    pub(crate) text: &'a str,
+}
+
+/// Point of connector `index` of `block`: 0 top, 1 right, 2 bottom, 3 left.
+pub(crate) fn connector_point(block: &JsBlock, index: usize) -> Node {
+    let (x, y) = (block.x, block.y);
+    match index {
+        0 => Node { x, y: y - block.height / 2 },
+        1 => Node { x: x + block.width / 2, y },
+        2 => Node { x, y: y + block.height / 2 },
+        _ => Node { x: x - block.width / 2, y },
+    }
+}
+
+/// Polyline an arrow is drawn along. Arrows laid out by the builder carry
+/// their own waypoints; the rest get an orthogonal route between their
+/// connectors, turning halfway through the gap above the target.
+pub(crate) fn route(diagram: &FullJson, arrow: &Arrow) -> Vec<Node> {
+    if !arrow.nodes.is_empty() {
+        return orthogonal(&arrow.nodes);
+    }
+    let (Some(from), Some(to)) = (diagram.blocks.get(arrow.start_index), diagram.blocks.get(arrow.end_index)) else {
+        return Vec::new();
+    };
+    let start = connector_point(from, arrow.start_connector_index);
+    let end = connector_point(to, arrow.end_connector_index);
+    let vertical_exit = matches!(arrow.start_connector_index, 0 | 2);
+    let vertical_entry = matches!(arrow.end_connector_index, 0 | 2);
+
+    let mut points = vec![Node { x: start.x, y: start.y }];
+    match (vertical_exit, vertical_entry) {
+        _ if start.y == end.y && !vertical_exit && !vertical_entry => {}
+        (true, true) => {
+            let turn = end.y - GAP / 2;
+            // пустая ветка match ушла бы сквозь блоки соседней ветки
+            let lane = diagram.blocks.iter()
+                .enumerate()
+                .filter(|(index, _)| *index != arrow.start_index && *index != arrow.end_index)
+                .filter(|(_, b)| b.y > start.y && b.y < end.y)
+                .filter(|(_, b)| (b.x - start.x).abs() < b.width / 2 || (b.x - end.x).abs() < b.width / 2)
+                .map(|(_, b)| b.x + b.width / 2 + GAP / 2)
+                .max();
+            if let Some(lane) = lane {
+                points.push(Node { x: start.x, y: start.y + GAP / 2 });
+                points.push(Node { x: lane, y: start.y + GAP / 2 });
+                points.push(Node { x: lane, y: turn });
+            } else if start.x != end.x {
+                points.push(Node { x: start.x, y: turn });
+            }
+            if start.x != end.x || lane.is_some() {
+                points.push(Node { x: end.x, y: turn });
+            }
+        }
+        (false, true) => {
+            let direction = if arrow.start_connector_index == 1 { 1 } else { -1 };
+            if (end.x - start.x) * direction > 0 && end.y > start.y {
+                points.push(Node { x: end.x, y: start.y });
+            } else {
+                let out = start.x + direction * GAP / 2;
+                let turn = end.y - GAP / 2;
+                points.push(Node { x: out, y: start.y });
+                points.push(Node { x: out, y: turn });
+                points.push(Node { x: end.x, y: turn });
+            }
+        }
+        (false, false) => {
+            let middle = (start.x + end.x) / 2;
+            points.push(Node { x: middle, y: start.y });
+            points.push(Node { x: middle, y: end.y });
+        }
+        (true, false) => points.push(Node { x: start.x, y: end.y }),
+    }
+    points.push(end);
+    points
+}
+
+/// Builder waypoints with a corner added wherever two of them differ in
+/// both coordinates: the line goes sideways first, then up or down.
+fn orthogonal(nodes: &[Node]) -> Vec<Node> {
+    let mut points: Vec<Node> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        if let Some(last) = points.last() {
+            if last.x != node.x && last.y != node.y {
+                points.push(Node { x: node.x, y: last.y });
+            }
+        }
+        points.push(Node { x: node.x, y: node.y });
+    }
+    points
+}
+
+/// Left, top, right and bottom edge of the blocks and arrow routes.
+pub(crate) fn bounds<'a>(
+    blocks: impl Iterator<Item = &'a JsBlock>,
+    routes: impl Iterator<Item = &'a Vec<Node>>,
+) -> (i32, i32, i32, i32) {
+    let boxes = blocks.map(|b| (b.x - b.width / 2, b.y - b.height / 2, b.x + b.width / 2, b.y + b.height / 2));
+    let points = routes.flatten().map(|p| (p.x, p.y, p.x, p.y));
+    boxes.chain(points)
+        .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+        .unwrap_or_default()
+}
+
+fn closed(points: &[(i32, i32)]) -> Figure {
+    Figure {
+        points: points.iter().map(|&(x, y)| (x as f32, y as f32)).collect(),
+        closed: true,
+    }
+}
+
+fn open(points: &[(i32, i32)]) -> Figure {
+    Figure { closed: false, ..closed(points) }
+}
+
+/// Quarter circle around `(cx, cy)` starting at angle `from` (clockwise on
+/// screen, 0 pointing right).
+fn arc(cx: f32, cy: f32, radius: f32, from: f32) -> impl Iterator<Item = (f32, f32)> {
+    (0..=ARC_STEPS).map(move |step| {
+        let angle = from + FRAC_PI_2 * step as f32 / ARC_STEPS as f32;
+        (cx + radius * angle.cos(), cy + radius * angle.sin())
+    })
+}
+
+/// Outline of a block by its programforyou type, as GOST 19.701 draws it.
+pub(crate) fn outline(block: &JsBlock) -> Vec<Figure> {
+    let (w, h) = (block.width, block.height);
+    let (x, y) = (block.x, block.y);
+    let (left, top) = (x - w / 2, y - h / 2);
+    let (right, bottom) = (left + w, top + h);
+    let bevel = h / 4;
+    match block.r#type.as_str() {
+        "Условие" => vec![closed(&[(x, top), (right, y), (x, bottom), (left, y)])],
+        "Ввод / вывод" => vec![closed(&[(left + bevel, top), (right, top), (right - bevel, bottom), (left, bottom)])],
+        "Начало / конец" => {
+            let radius = h as f32 / 2.0;
+            let (inner_left, inner_right) = (left as f32 + radius, right as f32 - radius);
+            let points = arc(inner_right, y as f32, radius, -FRAC_PI_2)
+                .chain(arc(inner_right, y as f32, radius, 0.0))
+                .chain(arc(inner_left, y as f32, radius, FRAC_PI_2))
+                .chain(arc(inner_left, y as f32, radius, 2.0 * FRAC_PI_2))
+                .collect();
+            vec![Figure { points, closed: true }]
+        }
+        "Цикл for" | "Подготовка" => vec![closed(&[
+            (left + bevel, top),
+            (right - bevel, top),
+            (right, y),
+            (right - bevel, bottom),
+            (left + bevel, bottom),
+            (left, y),
+        ])],
+        "Начало цикла" => vec![closed(&[
+            (left + bevel, top),
+            (right - bevel, top),
+            (right, top + bevel),
+            (right, bottom),
+            (left, bottom),
+            (left, top + bevel),
+        ])],
+        "Конец цикла" => vec![closed(&[
+            (left, top),
+            (right, top),
+            (right, bottom - bevel),
+            (right - bevel, bottom),
+            (left + bevel, bottom),
+            (left, bottom - bevel),
+        ])],
+        "Предопределённый процесс" => vec![
+            closed(&[(left, top), (right, top), (right, bottom), (left, bottom)]),
+            open(&[(left + w / 8, top), (left + w / 8, bottom)]),
+            open(&[(right - w / 8, top), (right - w / 8, bottom)]),
+        ],
+        "Соединитель" => {
+            let radius = w.min(h) as f32 / 2.0;
+            let points = (0..4)
+                .flat_map(|quarter| arc(x as f32, y as f32, radius, quarter as f32 * FRAC_PI_2).skip(1))
+                .collect();
+            vec![Figure { points, closed: true }]
+        }
+        "Межстраничный соединитель" => vec![closed(&[
+            (left, top),
+            (right, top),
+            (right, bottom - h / 3),
+            (x, bottom),
+            (left, bottom - h / 3),
+        ])],
+        "Комментарий" => {
+            let tick = left + PADDING as i32;
+            vec![open(&[(tick, top), (left, top), (left, bottom), (tick, bottom)])]
+        }
+        _ => vec![closed(&[(left, top), (right, top), (right, bottom), (left, bottom)])],
+    }
+}
+
+/// Lines of a block's text, centred on the block (comments: left aligned).
+pub(crate) fn caption_lines(block: &JsBlock) -> Vec<TextLine<'_>> {
+    let lines: Vec<&str> = block.text.lines().collect();
+    let (x, anchor) = if block.text_align == "left" {
+        ((block.x - block.width / 2) as f32 + PADDING, Anchor::Start)
+    } else {
+        (block.x as f32, Anchor::Middle)
+    };
+    // базовая линия первой строки: центр блока минус половина текста
+    let first = block.y as f32 - (lines.len().max(1) - 1) as f32 * LINE_HEIGHT / 2.0 + FONT_SIZE as f32 * 0.35;
+    lines.into_iter()
+        .enumerate()
+        .map(|(index, text)| TextLine { x, y: first + index as f32 * LINE_HEIGHT, anchor, text })
+        .collect()
+}
+
+/// Branch label next to where the arrow leaves its block; arrows leaving
+/// from the bottom (match arms) are labelled above their target instead,
+/// since several of them share the start.
+pub(crate) fn label_line<'a>(arrow: &'a Arrow, points: &[Node]) -> Option<TextLine<'a>> {
+    let start = points.first()?;
+    if arrow.label.is_empty() {
+        return None;
+    }
+    let (x, y, anchor) = match arrow.start_connector_index {
+        1 => (start.x + 4, start.y - 4, Anchor::Start),
+        3 => (start.x - 4, start.y - 4, Anchor::End),
+        _ => {
+            let corner = if points.len() > 2 { &points[points.len() - 2] } else { start };
+            (corner.x + 4, corner.y + FONT_SIZE, Anchor::Start)
+        }
+    };
+    Some(TextLine { x: x as f32, y: y as f32, anchor, text: &arrow.label })
+}
+
+/// Filled triangle at the end of a route, pointing along its last segment.
+pub(crate) fn arrowhead(points: &[Node]) -> Option<[(f32, f32); 3]> {
+    let [.., from, to] = points else {
+        return None;
+    };
+    let (dx, dy) = ((to.x - from.x) as f32, (to.y - from.y) as f32);
+    let length = dx.hypot(dy);
+    if length == 0.0 {
+        return None;
+    }
+    let (ux, uy) = (dx / length, dy / length);
+    let (tip_x, tip_y) = (to.x as f32, to.y as f32);
+    let (base_x, base_y) = (tip_x - ux * 10.0, tip_y - uy * 10.0);
+    Some([
+        (tip_x, tip_y),
+        (base_x - uy * 4.0, base_y + ux * 4.0),
+        (base_x + uy * 4.0, base_y - ux * 4.0),
+    ])
+}
@@ -0,0 +1,270 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+
+use crate::mk_json_blocks::{Arrow, FullJson, JsBlock};
+
+const TERMINATOR: &str = "Начало / конец";
+const DECISION: &str = "Условие";
+const COMMENT: &str = "Комментарий";
+const LOOP_TYPES: [&str; 2] = ["Цикл for", "Начало цикла"];
+const CONNECTOR_TYPES: [&str; 2] = ["Соединитель", "Межстраничный соединитель"];
+
+/// Connector indices of a block: 0 top, 1 right, 2 bottom, 3 left.
+const TOP: usize = 0;
+const RIGHT: usize = 1;
+const LEFT: usize = 3;
+
+/// One broken rule of ГОСТ 19.701-90 with the blocks it concerns.
+pub struct Violation {
+    pub blocks: Vec<usize>,
+    pub message: String,
+    pub rule: &'static str,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.blocks.is_empty() {
+            return write!(f, "схема: {} (ГОСТ 19.701-90, {})", self.message, self.rule);
+        }
+        let blocks = self.blocks.iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let noun = if self.blocks.len() == 1 { "блок" } else { "блоки" };
+        write!(f, "{noun} {blocks}: {} (ГОСТ 19.701-90, {})", self.message, self.rule)
+    }
+}
+
+/// Loads a programforyou diagram and checks it against the rules teachers
+/// ask for. Comment annotations and their dashed lines are not part of the
+/// flow and are skipped.
+pub fn check_diagram(json: &str) -> Result<Vec<Violation>> {
+    let diagram: FullJson = serde_json::from_str(json).context("Not a programforyou diagram")?;
+    let mut violations = Vec::new();
+
+    let flow: Vec<&Arrow> = diagram.arrows.iter().filter(|a| !a.dashed).collect();
+    for (index, arrow) in flow.iter().enumerate() {
+        for end in [arrow.start_index, arrow.end_index] {
+            if end >= diagram.blocks.len() {
+                violations.push(Violation {
+                    blocks: vec![end],
+                    message: format!("стрелка {index} ведёт к несуществующему блоку"),
+                    rule: "п. 4.2.1",
+                });
+            }
+        }
+    }
+    let flow: Vec<&Arrow> = flow.into_iter()
+        .filter(|a| a.start_index < diagram.blocks.len() && a.end_index < diagram.blocks.len())
+        .collect();
+
+    let mut has_start = false;
+    for (index, block) in diagram.blocks.iter().enumerate() {
+        let kind = block.r#type.as_str();
+        if kind == COMMENT {
+            continue;
+        }
+        let outgoing: Vec<&Arrow> = flow.iter().copied().filter(|a| a.start_index == index).collect();
+        let incoming: Vec<&Arrow> = flow.iter().copied().filter(|a| a.end_index == index).collect();
+        let is_connector = CONNECTOR_TYPES.contains(&kind);
+        // the return line of a loop enters its loop block from the left
+        let is_loop_return = |a: &&Arrow| LOOP_TYPES.contains(&kind) && a.end_connector_index == LEFT;
+
+        if kind == DECISION {
+            if outgoing.len() < 2 {
+                violations.push(Violation {
+                    blocks: vec![index],
+                    message: format!("решение «{}» имеет меньше двух выходов", one_line(&block.text)),
+                    rule: "п. 3.2.5",
+                });
+            } else if outgoing.len() == 2 && !labels_exits(block, &outgoing) {
+                violations.push(Violation {
+                    blocks: vec![index],
+                    message: format!("не все выходы решения «{}» подписаны", one_line(&block.text)),
+                    rule: "п. 4.3.2",
+                });
+            }
+        }
+
+        for arrow in incoming.iter().filter(|a| a.end_connector_index != TOP && !is_loop_return(a)) {
+            // слева вход допускается п. 4.2.2, но стандартное направление потока — сверху вниз
+            violations.push(Violation {
+                blocks: vec![arrow.start_index, index],
+                message: String::from("линия входит в блок не сверху"),
+                rule: if arrow.end_connector_index == LEFT { "п. 4.2.1" } else { "п. 4.2.2" },
+            });
+        }
+
+        let mut entries: Vec<usize> = incoming.iter()
+            .filter(|a| !is_loop_return(a))
+            .map(|a| a.end_connector_index)
+            .collect();
+        entries.sort_unstable();
+        entries.dedup();
+        if entries.len() > 1 {
+            violations.push(Violation {
+                blocks: vec![index],
+                message: String::from("несколько входов без слияния линий в одной точке"),
+                rule: "п. 4.2",
+            });
+        }
+
+        if kind == TERMINATOR {
+            match (incoming.is_empty(), outgoing.is_empty()) {
+                (true, false) => has_start = true,
+                (false, true) => {}
+                (true, true) => violations.push(Violation {
+                    blocks: vec![index],
+                    message: format!("терминатор «{}» не соединён ни с одним блоком", one_line(&block.text)),
+                    rule: "п. 3.4.2",
+                }),
+                (false, false) => violations.push(Violation {
+                    blocks: vec![index],
+                    message: format!("терминатор «{}» имеет и вход, и выход", one_line(&block.text)),
+                    rule: "п. 3.4.2",
+                }),
+            }
+        } else if !is_connector {
+            if incoming.is_empty() {
+                violations.push(Violation {
+                    blocks: vec![index],
+                    message: String::from("в блок не ведёт ни одна линия, начало схемы должно быть терминатором"),
+                    rule: "п. 3.4.2",
+                });
+            }
+            if outgoing.is_empty() {
+                violations.push(Violation {
+                    blocks: vec![index],
+                    message: String::from("из блока не выходит ни одна линия, конец схемы должен быть терминатором"),
+                    rule: "п. 3.4.2",
+                });
+            }
+        }
+    }
+
+    if !has_start && !diagram.blocks.is_empty() {
+        violations.push(Violation {
+            blocks: Vec::new(),
+            message: String::from("в схеме нет терминатора «начало»"),
+            rule: "п. 3.4.2",
+        });
+    }
+    Ok(violations)
+}
+
+/// The editor labels a decision itself, writing «да» and «нет» next to its
+/// side connectors, so one of the two exits has to leave by a side. Files
+/// without `labelsPosition` say nothing about the labels, and the exits of
+/// a multiway decision cannot be labelled in the format at all.
+fn labels_exits(block: &JsBlock, exits: &[&Arrow]) -> bool {
+    block.labels_position.is_none()
+        || exits.iter().any(|a| matches!(a.start_connector_index, RIGHT | LEFT))
+}
+
+fn one_line(text: &str) -> String {
+    text.replace('\n', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, CountedLoops, LoopStyle};
+    use crate::lang_vec_stuf::{Language, Rust};
+    use crate::mk_json_blocks::{build_diagram, create_json_blocks};
+    use crate::sizing::fit_blocks;
+
+    fn generate(source: &str, config: &Config) -> String {
+        let mut blocks = Rust.analyze_to_vec(source.to_string(), config);
+        fit_blocks(&mut blocks, config.wrap_width);
+        create_json_blocks(&build_diagram(blocks, config))
+    }
+
+    /// Violations of the JSON diagram generated for `source`.
+    fn check_source(source: &str) -> Vec<String> {
+        let json = generate(source, &Config::default());
+        check_diagram(&json).unwrap().iter().map(ToString::to_string).collect()
+    }
+
+    fn rules(json: &str) -> Vec<&'static str> {
+        check_diagram(json).unwrap().iter().map(|violation| violation.rule).collect()
+    }
+
+    #[test]
+    fn no_end_after_if_else_returning_in_both_branches() {
+        let source = "fn sign(x: i32) -> i32 {\n    if x < 0 {\n        return -1;\n    } else {\n        return 1;\n    }\n}\n";
+        assert_eq!(check_source(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn generated_diagrams_pass_the_check() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+        let mut sources: Vec<_> = std::fs::read_dir(&root).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "rs"))
+            .collect();
+        sources.extend(["lab5-rs", "lab9-rs"].map(|name| root.join(name)));
+        let configs = [
+            Config::default(),
+            Config { loop_style: LoopStyle::Boundary, ..Config::default() },
+            Config { counted_loops: CountedLoops::Decomposed, ..Config::default() },
+        ];
+        for path in sources {
+            let source = std::fs::read_to_string(&path).unwrap();
+            for config in &configs {
+                let violations: Vec<String> = check_diagram(&generate(&source, config)).unwrap()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                assert!(violations.is_empty(), "{}: {violations:?}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn decision_needs_two_labelled_exits() {
+        let json = generate("fn f(x: i32) {\n    if x > 0 {\n        go();\n    }\n}\n", &Config::default());
+        let mut diagram: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let decision = diagram["blocks"].as_array().unwrap().iter()
+            .position(|block| block["type"] == DECISION)
+            .unwrap();
+        // выходы снизу остаются без подписей, которые редактор ставит по бокам
+        let arrows = diagram["arrows"].as_array_mut().unwrap();
+        for arrow in arrows.iter_mut().filter(|arrow| arrow["startIndex"] == decision) {
+            arrow["startConnectorIndex"] = 2.into();
+        }
+        assert_eq!(rules(&diagram.to_string()), ["п. 4.3.2"]);
+        diagram["blocks"][decision].as_object_mut().unwrap().remove("labelsPosition");
+        assert_eq!(rules(&diagram.to_string()), Vec::<&str>::new());
+
+        let arrows = diagram["arrows"].as_array_mut().unwrap();
+        let exit = arrows.iter().position(|arrow| arrow["startIndex"] == decision).unwrap();
+        arrows.remove(exit);
+        assert!(rules(&diagram.to_string()).contains(&"п. 3.2.5"));
+    }
+
+    #[test]
+    fn diagrams_made_in_the_editor_are_checked() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test/outfiles_bac");
+        for name in ["example.json", "example_if_else.json", "еще 1 нормальный пример.json"] {
+            let json = std::fs::read_to_string(root.join(name)).unwrap();
+            let rules = rules(&json);
+            assert!(!rules.contains(&"п. 4.3.2"), "{name}: {rules:?}");
+        }
+    }
+
+    #[test]
+    fn flow_must_start_and_end_with_terminators() {
+        let json = generate("fn f() {\n    go();\n}\n", &Config::default());
+        let mut diagram: serde_json::Value = serde_json::from_str(&json).unwrap();
+        diagram["arrows"].as_array_mut().unwrap().clear();
+        let rules = rules(&diagram.to_string());
+        assert!(rules.iter().all(|rule| *rule == "п. 3.4.2"), "{rules:?}");
+        assert_eq!(rules.len(), 5);
+    }
+
+    #[test]
+    fn other_json_is_rejected() {
+        assert!(check_diagram("{\"nodes\": []}").is_err());
+    }
+}
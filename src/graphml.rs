@@ -0,0 +1,121 @@
+use crate::geometry::route;
+use crate::mk_json_blocks::{FullJson, JsBlock};
+use crate::sizing::FONT_SIZE;
+use crate::svg::escape;
+
+/// Writes a diagram as GraphML with the yEd extensions: every block is a
+/// node of the yEd flowchart palette with the geometry and text of the
+/// layout, every arrow an edge with its bends, so the diagram opens in yEd
+/// as laid out here and can be laid out again there. The source span of a
+/// block goes into the node property `source`.
+pub fn render_graphml(diagram: &FullJson) -> String {
+    let mut graphml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\" \
+         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xmlns:y=\"http://www.yworks.com/xml/graphml\" \
+         xsi:schemaLocation=\"http://graphml.graphdrawing.org/xmlns \
+         http://www.yworks.com/xml/schema/graphml/1.1/ygraphml.xsd\">\n  \
+         <key id=\"d0\" for=\"node\" yfiles.type=\"nodegraphics\"/>\n  \
+         <key id=\"d1\" for=\"node\" attr.name=\"source\" attr.type=\"string\"/>\n  \
+         <key id=\"d2\" for=\"edge\" yfiles.type=\"edgegraphics\"/>\n  \
+         <graph id=\"G\" edgedefault=\"directed\">\n",
+    );
+
+    for (index, block) in diagram.blocks.iter().enumerate() {
+        graphml.push_str(&format!("    <node id=\"n{index}\">\n"));
+        if let Some(span) = block.source {
+            graphml.push_str(&format!("      <data key=\"d1\">{span}</data>\n"));
+        }
+        // скобка комментария рисуется без заливки
+        let fill = if block.r#type == "Комментарий" {
+            "transparent=\"true\""
+        } else {
+            "color=\"#FFFFFF\" transparent=\"false\""
+        };
+        let alignment = if block.text_align == "left" { "left" } else { "center" };
+        graphml.push_str(&format!(
+            "      <data key=\"d0\">\n        \
+             <y:GenericNode configuration=\"com.yworks.flowchart.{}\">\n          \
+             <y:Geometry x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>\n          \
+             <y:Fill {fill}/>\n          \
+             <y:BorderStyle color=\"#000000\" type=\"line\" width=\"1.5\"/>\n          \
+             <y:NodeLabel alignment=\"{alignment}\" autoSizePolicy=\"content\" fontFamily=\"Arial\" \
+             fontSize=\"{FONT_SIZE}\" modelName=\"internal\" modelPosition=\"c\">{}</y:NodeLabel>\n        \
+             </y:GenericNode>\n      \
+             </data>\n    \
+             </node>\n",
+            shape(block),
+            block.x - block.width / 2,
+            block.y - block.height / 2,
+            block.width,
+            block.height,
+            escape(&block.text)
+        ));
+    }
+
+    for (index, arrow) in diagram.arrows.iter().enumerate() {
+        let points = route(diagram, arrow);
+        let (Some(from), Some(to)) = (diagram.blocks.get(arrow.start_index), diagram.blocks.get(arrow.end_index)) else {
+            continue;
+        };
+        let [first, bends @ .., last] = points.as_slice() else {
+            continue;
+        };
+        // концы ребра задаются смещением от центра блока, изгибы — абсолютно
+        graphml.push_str(&format!(
+            "    <edge id=\"e{index}\" source=\"n{}\" target=\"n{}\">\n      \
+             <data key=\"d2\">\n        \
+             <y:PolyLineEdge>\n          \
+             <y:Path sx=\"{}\" sy=\"{}\" tx=\"{}\" ty=\"{}\">\n",
+            arrow.start_index,
+            arrow.end_index,
+            first.x - from.x,
+            first.y - from.y,
+            last.x - to.x,
+            last.y - to.y
+        ));
+        for bend in bends {
+            graphml.push_str(&format!("            <y:Point x=\"{}\" y=\"{}\"/>\n", bend.x, bend.y));
+        }
+        let (line, target) = if arrow.dashed { ("dashed", "none") } else { ("line", "standard") };
+        graphml.push_str(&format!(
+            "          </y:Path>\n          \
+             <y:LineStyle color=\"#000000\" type=\"{line}\" width=\"1.5\"/>\n          \
+             <y:Arrows source=\"none\" target=\"{target}\"/>\n"
+        ));
+        if !arrow.label.is_empty() {
+            graphml.push_str(&format!(
+                "          <y:EdgeLabel fontFamily=\"Arial\" fontSize=\"{FONT_SIZE}\" \
+                 modelName=\"six_pos\" modelPosition=\"shead\">{}</y:EdgeLabel>\n",
+                escape(&arrow.label)
+            ));
+        }
+        graphml.push_str(
+            "          <y:BendStyle smoothed=\"false\"/>\n        \
+             </y:PolyLineEdge>\n      \
+             </data>\n    \
+             </edge>\n",
+        );
+    }
+
+    graphml.push_str("  </graph>\n</graphml>\n");
+    graphml
+}
+
+/// Shape of the yEd flowchart palette drawn like the GOST block.
+fn shape(block: &JsBlock) -> &'static str {
+    match block.r#type.as_str() {
+        "Условие" => "decision",
+        "Ввод / вывод" => "data",
+        "Начало / конец" => "terminator",
+        "Цикл for" | "Подготовка" => "preparation",
+        "Предопределённый процесс" => "predefinedProcess",
+        "Начало цикла" => "loopLimit",
+        "Конец цикла" => "loopLimitEnd",
+        "Соединитель" => "onPageReference",
+        "Межстраничный соединитель" => "offPageReference",
+        "Комментарий" => "annotation",
+        _ => "process",
+    }
+}
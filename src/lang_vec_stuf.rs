@@ -1,7 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use tree_sitter::{Node, Parser};
 
-#[derive(Debug, PartialEq)]
+use crate::config::{Config, CountedLoops, LoopStyle};
+use crate::wrap::caption_text;
+use crate::structure::{diverges, functions};
+
+/// Letters used to name paired blocks, without the ones easily confused
+/// with digits or each other.
+pub const LABEL_LETTERS: [char; 20] = [
+    'А', 'Б', 'В', 'Г', 'Д', 'Е', 'Ж', 'И', 'К', 'Л',
+    'М', 'Н', 'П', 'Р', 'С', 'Т', 'У', 'Ф', 'Х', 'Ц',
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BlockType {
     Start,
     End,
@@ -9,8 +20,28 @@ pub enum BlockType {
     Print,
     Condition,
     Cycle,
+    /// Test of a counted loop drawn as init → decision → body → increment;
+    /// closed like `Cycle`, with the back-edge returning to the decision.
+    LoopTest,
     Else,
     EndMatchArm,
+    /// Start of a match arm; the text is the arm pattern, written on the
+    /// edge leading into the arm.
+    Case,
+    Switch,
+    Parallel,
+    Terminate,
+    Input,
+    Subroutine,
+    LoopStart,
+    LoopEnd,
+    /// GOST "подготовка": initialisation of variables before they are used.
+    Preparation,
+    /// On-page connector. Connectors come in pairs with the same text: the
+    /// first one ends the flow, the second one resumes it.
+    Connector,
+    /// Off-page connector, paired like `Connector`.
+    OffPageConnector,
 }
 
 #[derive(Debug)]
@@ -19,6 +50,107 @@ pub struct CodeBlock {
     pub text: String,
     pub x: i32,
     pub y: i32,
+    /// Function name this block links to: the function itself on `Start`,
+    /// the callee on `Subroutine`.
+    pub link: Option<String>,
+    /// Source comment drawn as a GOST comment annotation next to the block.
+    pub comment: Option<String>,
+    /// Width and height, filled in by `sizing::fit_blocks`.
+    pub size: (i32, i32),
+    /// Source code the block was made from; `None` for connectors added by
+    /// pagination.
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jump {
+    Break,
+    Continue,
+}
+
+/// Label of a loop header such as `'outer: for x in xs`, apostrophe included.
+pub fn loop_label(header: &str) -> Option<&str> {
+    header.match_indices('\'').find_map(|(start, _)| {
+        let rest = &header[start + 1..];
+        let length = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+        (length > 0 && rest[length..].starts_with(':')).then(|| &header[start..=start + length])
+    })
+}
+
+/// Range of the source, 1-based lines and columns counted in characters.
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}-{}:{}", self.start.0, self.start.1, self.end.0, self.end.1)
+    }
+}
+
+impl CodeBlock {
+    /// Structure markers only steer the layout and are not drawn.
+    pub fn is_marker(&self) -> bool {
+        match self.r#type {
+            BlockType::Else | BlockType::EndMatchArm | BlockType::Case => true,
+            BlockType::End => matches!(
+                self.text.as_str(),
+                "end if" | "end else" | "end match" | "end join"
+            ),
+            _ => false,
+        }
+    }
+
+    /// `break` or `continue` of an enclosing loop, with the loop label if
+    /// the jump names one.
+    pub fn loop_jump(&self) -> Option<(Jump, Option<&str>)> {
+        if self.r#type != BlockType::Action {
+            return None;
+        }
+        let mut words = self.text.split_whitespace();
+        let jump = match words.next()? {
+            "break" => Jump::Break,
+            "continue" => Jump::Continue,
+            _ => return None,
+        };
+        Some((jump, words.next().filter(|word| word.starts_with('\''))))
+    }
+
+    /// Loop ends carry the coordinates of their loop block as `"x:y"`,
+    /// followed by `":step"` when the loop has a known counter increment.
+    pub fn loop_origin(&self) -> Option<(i32, i32)> {
+        if self.r#type != BlockType::End {
+            return None;
+        }
+        let mut parts = self.text.splitn(3, ':');
+        let x = parts.next()?.parse().ok()?;
+        let y = parts.next()?.parse().ok()?;
+        Some((x, y))
+    }
+
+    /// Text the block stands for, before wrapping.
+    pub fn full_caption(&self) -> &str {
+        match self.r#type {
+            BlockType::Print if self.text.is_empty() => "Вывод строки",
+            BlockType::End if self.loop_origin().is_some() => self.loop_step().unwrap_or_default(),
+            BlockType::End if self.text.is_empty() || self.text == "}" => "Конец",
+            _ => &self.text,
+        }
+    }
+
+    /// Text as it is written inside the drawn block, wrapped to `width`
+    /// characters per line.
+    pub fn caption(&self, width: usize) -> String {
+        caption_text(self.full_caption(), width)
+    }
+
+    /// Counter increment drawn at the end of the loop body, see `loop_origin`.
+    pub fn loop_step(&self) -> Option<&str> {
+        self.loop_origin()?;
+        self.text.splitn(3, ':').nth(2)
+    }
 }
 
 struct DiagramBuilder {
@@ -26,14 +158,31 @@ struct DiagramBuilder {
     blocks: Vec<CodeBlock>,
     position: (i32, i32),
     block_stack: Vec<BlockScope>,
+    functions: HashSet<String>,
+    /// Names `std::process::exit`/`abort` are imported under.
+    exits: HashSet<String>,
+    /// Variables holding a standard input handle, see `stdin_handles`.
+    stdin: HashSet<String>,
+    loop_count: usize,
+    /// Comments seen since the last block, waiting for the block they precede.
+    comments: Vec<String>,
+    /// First block of the last processed statement, the one a comment
+    /// trailing the statement annotates.
+    statement: usize,
+    /// Source of the node being processed, given to every block it adds.
+    span: Option<Span>,
+    /// Inside reparsed macro arguments, whose positions are not source
+    /// positions: the blocks keep the span of the macro invocation.
+    in_snippet: bool,
 }
 
 #[derive(Debug)]
 enum BlockScope {
     Function,
     If(i32),
-    Loop(i32),
-    Match(i32, usize),
+    /// Open loop with the block type and text that close it.
+    Loop(BlockType, String),
+    Match,
 }
 
 impl DiagramBuilder {
@@ -43,6 +192,14 @@ impl DiagramBuilder {
             blocks: Vec::new(),
             position: (0, 0),
             block_stack: Vec::new(),
+            functions: HashSet::new(),
+            exits: HashSet::new(),
+            stdin: HashSet::new(),
+            loop_count: 0,
+            comments: Vec::new(),
+            statement: 0,
+            span: None,
+            in_snippet: false,
         }
     }
 
@@ -52,8 +209,86 @@ impl DiagramBuilder {
             text: text.into(),
             x: self.position.0,
             y: self.position.1,
+            link: None,
+            comment: None,
+            size: (0, 0),
+            span: self.span,
         };
         self.blocks.push(block);
+        if !self.comments.is_empty() && !self.blocks[self.blocks.len() - 1].is_marker() {
+            let comment = self.comments.join("\n");
+            self.comments.clear();
+            self.append_comment(comment);
+        }
+    }
+
+    /// Adds `comment` to the annotation of the last drawn block.
+    fn append_comment(&mut self, comment: String) {
+        if let Some(index) = self.blocks.iter().rposition(|b| !b.is_marker()) {
+            self.annotate(index, comment);
+        }
+    }
+
+    /// Adds a trailing `comment` to the head block of the statement it
+    /// follows, not to the last block of a loop or branching it ends with.
+    fn append_trailing_comment(&mut self, comment: String) {
+        let head = self.blocks.get(self.statement..)
+            .and_then(|blocks| blocks.iter().position(|b| !b.is_marker()));
+        match head {
+            Some(offset) => self.annotate(self.statement + offset, comment),
+            None => self.append_comment(comment),
+        }
+    }
+
+    fn annotate(&mut self, index: usize, comment: String) {
+        let block = &mut self.blocks[index];
+        block.comment = Some(match block.comment.take() {
+            Some(existing) => format!("{existing}\n{comment}"),
+            None => comment,
+        });
+    }
+
+    fn add_linked_block(&mut self, block_type: BlockType, text: impl Into<String>, link: String) {
+        self.add_block(block_type, text);
+        if let Some(block) = self.blocks.last_mut() {
+            block.link = Some(link);
+        }
+    }
+
+    fn node_text(&self, node: &Node) -> String {
+        node.utf8_text(self.source.as_bytes())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn span_of(&self, node: &Node) -> Span {
+        let position = |byte: usize, point: tree_sitter::Point| {
+            let line = &self.source[byte - point.column..byte];
+            (point.row + 1, line.chars().count() + 1)
+        };
+        Span {
+            start: position(node.start_byte(), node.start_position()),
+            end: position(node.end_byte(), node.end_position()),
+        }
+    }
+
+    /// Text of `node` from its beginning up to (not including) `until`,
+    /// used for headers like `if cond` or `for i in 0..n`.
+    fn header_text(&self, node: &Node, until: &Node) -> String {
+        without_awaits(node, until.start_byte(), &self.source)
+            .trim()
+            .to_string()
+    }
+
+    /// Name for the next loop-boundary pair of the current function:
+    /// "Цикл А", "Цикл Б", ... and numbers once the letters run out.
+    fn next_loop_label(&mut self) -> String {
+        let index = self.loop_count;
+        self.loop_count += 1;
+        match LABEL_LETTERS.get(index) {
+            Some(letter) => format!("Цикл {letter}"),
+            None => format!("Цикл {}", index + 1),
+        }
     }
 
     fn enter_scope(&mut self, scope: BlockScope, x_shift: i32, y_shift: i32) {
@@ -63,36 +298,36 @@ impl DiagramBuilder {
     }
 
     fn exit_scope(&mut self) {
+        // comments after the last statement of a scope have nothing to precede
+        self.comments.clear();
         if let Some(scope) = self.block_stack.pop() {
             match scope {
                 BlockScope::Function => self.position.1 += 100,
                 BlockScope::If(depth) => {
                     self.position.0 -= 100 * depth;
-                    self.position.1 += 200;
                 }
-                BlockScope::Loop(_) => {
+                BlockScope::Loop(..) => {
                     self.position.1 += 100;
                 }
-                BlockScope::Match(_, _) => {
-                    self.position.0 -= 300;
-                    self.position.1 += 50;
-                }
+                BlockScope::Match => {}
             }
         }
     }
 }
 
+type Handler = fn(&AstProcessor, &Node, &mut DiagramBuilder);
+
 struct AstProcessor {
-    handlers: HashMap<&'static str, fn(&Node, &mut DiagramBuilder)>,
+    handlers: HashMap<&'static str, Handler>,
+    config: Config,
 }
 
 impl AstProcessor {
-    fn new() -> Self {
-        let mut handlers = HashMap::new();
+    fn new(config: Config) -> Self {
+        let mut handlers: HashMap<&'static str, Handler> = HashMap::new();
 
         handlers.insert("function_item", Self::handle_function);
         handlers.insert("if_expression", Self::handle_if);
-        handlers.insert("else_clause", Self::handle_else);
         handlers.insert("for_expression", Self::handle_loop);
         handlers.insert("while_expression", Self::handle_loop);
         handlers.insert("loop_expression", Self::handle_loop);
@@ -101,80 +336,445 @@ impl AstProcessor {
         handlers.insert("match_expression", Self::handle_match);
         handlers.insert("match_arm", Self::handle_match_arm);
 
-        Self { handlers }
+        Self { handlers, config }
     }
 
     fn process_node(&self, node: &Node, builder: &mut DiagramBuilder) {
         let kind = node.kind();
+        let outer = builder.span;
+        if !builder.in_snippet {
+            builder.span = Some(builder.span_of(node));
+        }
 
         if let Some(handler) = self.handlers.get(kind) {
-            handler(node, builder);
+            handler(self, node, builder);
         } else {
             self.handle_generic(node, builder);
         }
-
-        self.process_children(node, builder);
+        builder.span = outer;
     }
 
     fn process_children(&self, node: &Node, builder: &mut DiagramBuilder) {
         let mut cursor = node.walk();
         for child in node.children(&mut cursor) {
+            let first = builder.blocks.len();
             self.process_node(&child, builder);
+            if !child.kind().ends_with("comment") {
+                builder.statement = first;
+            }
+        }
+    }
+
+    /// Processes the body of a branch, which is either a block or a single
+    /// expression (match arms, `select!` arms).
+    fn process_body(&self, node: &Node, builder: &mut DiagramBuilder) {
+        if node.kind() == "block" {
+            self.process_children(node, builder);
+        } else if self.handlers.contains_key(node.kind()) {
+            self.process_node(node, builder);
+        } else {
+            self.handle_statement(node, builder);
+        }
+    }
+
+    /// Parses a fragment of code that tree-sitter left as a raw token tree
+    /// (macro arguments) and processes it as a function body.
+    fn process_snippet(&self, code: &str, builder: &mut DiagramBuilder) {
+        let source = format!("fn snippet() {{\n{code}\n}}");
+        let Some(tree) = rust_parser().parse(&source, None) else {
+            return;
+        };
+
+        let saved = std::mem::replace(&mut builder.source, source);
+        let in_snippet = std::mem::replace(&mut builder.in_snippet, true);
+        if let Some(body) = tree
+            .root_node()
+            .named_child(0)
+            .and_then(|function| function.child_by_field_name("body"))
+        {
+            self.process_children(&body, builder);
         }
+        builder.in_snippet = in_snippet;
+        builder.source = saved;
     }
 
     fn handle_generic(&self, node: &Node, builder: &mut DiagramBuilder) {
-        let text = node.utf8_text(builder.source.as_bytes()).unwrap_or_default();
-        if !text.trim().is_empty() {
+        let in_block = node.parent().is_some_and(|p| p.kind() == "block");
+
+        match node.kind() {
+            "expression_statement" => match node.named_child(0) {
+                Some(inner) if self.handlers.contains_key(inner.kind()) => {
+                    self.process_node(&inner, builder)
+                }
+                Some(inner) if concurrency_macro(&inner, &builder.source).is_some() => {
+                    self.handle_concurrency(&inner, builder, "")
+                }
+                Some(inner) if self.config.expand_iterators => {
+                    match iterator_chain(&inner, &builder.source) {
+                        Some(chain) => self.handle_iterator_chain(chain, None, builder),
+                        None => self.handle_statement(node, builder),
+                    }
+                }
+                _ => self.handle_statement(node, builder),
+            },
+            "let_declaration" => match node.child_by_field_name("value") {
+                Some(value) if concurrency_macro(&value, &builder.source).is_some() => {
+                    let prefix = builder.header_text(node, &value);
+                    self.handle_concurrency(&value, builder, &format!("{prefix} "))
+                }
+                Some(value) if self.config.expand_iterators => {
+                    let binding = node
+                        .child_by_field_name("pattern")
+                        .map(|pattern| builder.node_text(&pattern));
+                    match iterator_chain(&value, &builder.source) {
+                        Some(chain) => self.handle_iterator_chain(chain, binding, builder),
+                        None => self.handle_statement(node, builder),
+                    }
+                }
+                _ => self.handle_statement(node, builder),
+            },
+            "line_comment" | "block_comment" => self.handle_comment(node, builder),
+            kind if in_block && node.is_named() && is_expression(kind) => {
+                self.handle_statement(node, builder)
+            }
+            _ => self.process_children(node, builder),
+        }
+    }
+
+    /// Comments annotate the block they precede; a comment on the same line
+    /// as the end of the previous statement annotates that statement.
+    /// A `///` doc comment thus ends up on the Start block of its function.
+    fn handle_comment(&self, node: &Node, builder: &mut DiagramBuilder) {
+        let Some(text) = comment_text(&builder.node_text(node)) else {
+            return;
+        };
+        if is_trailing(node) {
+            builder.append_trailing_comment(text);
+        } else {
+            builder.comments.push(text);
+        }
+    }
+
+    fn handle_statement(&self, node: &Node, builder: &mut DiagramBuilder) {
+        self.emit_hidden_steps(node, builder);
+        if is_pure_await(node) {
+            return;
+        }
+
+        let text = without_awaits(node, node.end_byte(), &builder.source);
+        let text = text.trim().trim_end_matches(';').trim_end();
+        if text.is_empty() {
+            return;
+        }
+
+        if let Some(target) = input_target(node, &builder.source, &builder.stdin) {
+            builder.add_block(BlockType::Input, format!("Ввод {target}"));
+        } else if is_diverging_call(node, &builder.source, &builder.exits) {
+            builder.add_block(BlockType::Terminate, text);
+        } else if let Some(callee) = local_callee(node, &builder.source, &builder.functions) {
+            builder.add_linked_block(BlockType::Subroutine, text, callee);
+        } else {
             builder.add_block(BlockType::Action, text);
-            builder.position.1 += 50;
         }
+        builder.position.1 += 100;
     }
 
-    fn handle_function(node: &Node, builder: &mut DiagramBuilder) {
-        let name = node.child(1).and_then(|n| n.utf8_text(builder.source.as_bytes()).ok())
-            .unwrap_or("anonymous");
+    /// Emits the steps hidden inside an expression in evaluation order: an
+    /// action for every `.await`, so the suspension points are visible, and
+    /// with `unwrap_decisions` a failure decision for every `unwrap`/`expect`.
+    /// Closures and `async` blocks are skipped: their bodies do not run here.
+    fn emit_hidden_steps(&self, node: &Node, builder: &mut DiagramBuilder) {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            if !matches!(child.kind(), "closure_expression" | "async_block") {
+                self.emit_hidden_steps(&child, builder);
+            }
+        }
 
-        builder.add_block(BlockType::Start, name);
-        builder.enter_scope(BlockScope::Function, 0, 100);
+        if node.kind() == "await_expression" {
+            if let Some(future) = node.named_child(0) {
+                let text = format!("ожидание: {}", without_awaits(&future, future.end_byte(), &builder.source));
+                builder.add_block(BlockType::Action, text);
+                builder.position.1 += 100;
+            }
+        }
+        if self.config.unwrap_decisions {
+            if let Some((receiver, message)) = unwrap_call(node, &builder.source) {
+                self.emit_failure_decision(&receiver, message, builder);
+            }
+        }
     }
 
-    fn handle_if(node: &Node, builder: &mut DiagramBuilder) {
+    /// `value.expect(msg)` as "value — ошибка?" whose "yes" branch ends the
+    /// program with a panic.
+    fn emit_failure_decision(&self, receiver: &str, message: Option<String>, builder: &mut DiagramBuilder) {
         let depth = builder.block_stack.iter()
             .filter(|s| matches!(s, BlockScope::If(_)))
             .count() as i32 + 1;
 
-        builder.add_block(BlockType::Condition, "if");
+        builder.add_block(BlockType::Condition, format!("{receiver} — ошибка?"));
         builder.enter_scope(BlockScope::If(depth), 100 * depth, 100);
+        let panic = match message {
+            Some(message) => format!("panic: {message}"),
+            None => "panic".to_string(),
+        };
+        builder.add_block(BlockType::Terminate, panic);
+        builder.position.1 += 100;
+        builder.add_block(BlockType::End, "end if");
+        builder.exit_scope();
     }
 
-    fn handle_else(node: &Node, builder: &mut DiagramBuilder) {
-        if let Some(BlockScope::If(depth)) = builder.block_stack.last() {
-            builder.add_block(BlockType::Else, "else");
-            builder.position.0 -= 100 * depth;
+    fn handle_function(&self, node: &Node, builder: &mut DiagramBuilder) {
+        let function_name = node
+            .child_by_field_name("name")
+            .map(|n| builder.node_text(&n))
+            .unwrap_or_else(|| "anonymous".to_string());
+        let name = if function_name == "main" { "Начало".to_string() } else { function_name.clone() };
+
+        let mut cursor = node.walk();
+        let is_async = node.children(&mut cursor).any(|child| {
+            child.kind() == "function_modifiers" && builder.node_text(&child).contains("async")
+        });
+        let title = if is_async { format!("async {name}") } else { name };
+
+        builder.position.0 = 0;
+        builder.loop_count = 0;
+        let first = builder.blocks.len();
+        builder.add_linked_block(BlockType::Start, title, function_name);
+        builder.enter_scope(BlockScope::Function, 0, 100);
+
+        if let Some(body) = node.child_by_field_name("body") {
+            if self.config.group_declarations {
+                self.process_function_body(&body, builder);
+            } else {
+                self.process_children(&body, builder);
+            }
+        }
+
+        // «Конец» нужен, только если поток может дойти до конца тела
+        let falls_through = functions(&builder.blocks[first..])
+            .first()
+            .is_none_or(|function| !diverges(&function.body));
+        if falls_through {
+            builder.add_block(BlockType::End, "");
+        }
+        builder.exit_scope();
+    }
+
+    /// Processes a function body, drawing the `let` declarations it opens
+    /// with as one preparation block. A declaration that needs more than
+    /// a single action (input, calls of the analysed file, expanded
+    /// iterators...) ends the group and is drawn as usual.
+    fn process_function_body(&self, body: &Node, builder: &mut DiagramBuilder) {
+        let mut declarations = Vec::new();
+        // comments of the grouped declarations and those seen since the
+        // last of them, which precede whatever comes next
+        let (mut annotations, mut pending) = (Vec::new(), Vec::new());
+        let mut cursor = body.walk();
+        let mut children = body.children(&mut cursor).peekable();
+
+        while let Some(child) = children.peek() {
+            match child.kind() {
+                "{" => {}
+                "line_comment" | "block_comment" => {
+                    if let Some(text) = comment_text(&builder.node_text(child)) {
+                        let follows_declaration = child.prev_sibling()
+                            .is_some_and(|prev| prev.kind() == "let_declaration");
+                        if follows_declaration && is_trailing(child) {
+                            annotations.push(text);
+                        } else {
+                            pending.push(text);
+                        }
+                    }
+                }
+                "let_declaration" => match self.plain_declaration(child, builder) {
+                    Some((declaration, comment)) => {
+                        declarations.push(declaration);
+                        annotations.append(&mut pending);
+                        annotations.extend(comment);
+                    }
+                    None => break,
+                },
+                _ => break,
+            }
+            children.next();
+        }
+
+        if !declarations.is_empty() {
+            builder.comments.append(&mut annotations);
+            builder.statement = builder.blocks.len();
+            builder.add_block(BlockType::Preparation, declarations.join("\n"));
             builder.position.1 += 100;
         }
+        builder.comments.append(&mut pending);
+        for child in children {
+            let first = builder.blocks.len();
+            self.process_node(&child, builder);
+            if !child.kind().ends_with("comment") {
+                builder.statement = first;
+            }
+        }
+    }
+
+    /// Text and comment of a declaration that would be drawn as a single
+    /// action.
+    fn plain_declaration(&self, node: &Node, builder: &DiagramBuilder) -> Option<(String, Option<String>)> {
+        let mut scratch = DiagramBuilder::new(builder.source.clone());
+        scratch.functions.clone_from(&builder.functions);
+        scratch.exits.clone_from(&builder.exits);
+        scratch.stdin.clone_from(&builder.stdin);
+        self.process_node(node, &mut scratch);
+
+        match scratch.blocks.as_slice() {
+            [block] if block.r#type == BlockType::Action => Some((block.text.clone(), block.comment.clone())),
+            _ => None,
+        }
+    }
+
+    fn handle_if(&self, node: &Node, builder: &mut DiagramBuilder) {
+        let depth = builder.block_stack.iter()
+            .filter(|s| matches!(s, BlockScope::If(_)))
+            .count() as i32 + 1;
+
+        let Some(consequence) = node.child_by_field_name("consequence") else {
+            return;
+        };
+        if let Some(condition) = node.child_by_field_name("condition") {
+            self.emit_hidden_steps(&condition, builder);
+        }
+
+        builder.add_block(BlockType::Condition, builder.header_text(node, &consequence));
+        let (x, y) = builder.position;
+        builder.enter_scope(BlockScope::If(depth), 100 * depth, 100);
+        self.process_children(&consequence, builder);
+
+        if let Some(alternative) = node.child_by_field_name("alternative") {
+            self.handle_else(&alternative, builder, (x, y), depth);
+            builder.add_block(BlockType::End, "end else");
+        } else {
+            builder.add_block(BlockType::End, "end if");
+        }
+        builder.exit_scope();
+    }
+
+    /// Lays the else branch out on the opposite side of the condition at
+    /// `origin`, starting on the same row as the then branch.
+    fn handle_else(&self, node: &Node, builder: &mut DiagramBuilder, origin: (i32, i32), depth: i32) {
+        let then_bottom = builder.position.1;
+        builder.add_block(BlockType::Else, "else");
+        builder.position = (origin.0 - 100 * depth, origin.1 + 100);
+
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            self.process_body(&child, builder);
+        }
+
+        builder.position.0 = origin.0 + 100 * depth;
+        builder.position.1 = builder.position.1.max(then_bottom);
+    }
+
+    fn handle_loop(&self, node: &Node, builder: &mut DiagramBuilder) {
+        let Some(body) = node.child_by_field_name("body") else {
+            return;
+        };
+        for field in ["value", "condition"] {
+            if let Some(header) = node.child_by_field_name(field) {
+                self.emit_hidden_steps(&header, builder);
+            }
+        }
+
+        let counted = match (node.child_by_field_name("pattern"), node.child_by_field_name("value")) {
+            (Some(pattern), Some(value)) => {
+                counted_range(&builder.node_text(&pattern), &value, &builder.source)
+            }
+            _ => None,
+        };
+
+        self.enter_loop(builder.header_text(node, &body), counted, builder);
+        self.process_children(&body, builder);
+        self.exit_loop(builder);
     }
 
-    fn handle_loop(node: &Node, builder: &mut DiagramBuilder) {
-        let loop_type = match node.kind() {
-            "for_expression" => "for",
-            "while_expression" => "while",
-            _ => "loop",
+    /// Opens a loop in the configured style: a loop block that the end of
+    /// the body returns to, the opening block of a loop-boundary pair, or
+    /// for counted loops the explicit init and test of the counter.
+    fn enter_loop(&self, header: String, counted: Option<CountedRange>, builder: &mut DiagramBuilder) {
+        if let Some(range) = &counted {
+            if self.config.counted_loops == CountedLoops::Decomposed {
+                builder.add_block(BlockType::Preparation, range.init.clone());
+                builder.position.1 += 100;
+                // метка остаётся видна, чтобы break и continue нашли свой цикл
+                let test = match loop_label(&header) {
+                    Some(label) => format!("{label}: {}", range.test),
+                    None => range.test.clone(),
+                };
+                builder.add_block(BlockType::LoopTest, test);
+                let closing = format!("{}:{}:{}", builder.position.0, builder.position.1, range.step);
+                builder.enter_scope(BlockScope::Loop(BlockType::End, closing), 0, 100);
+                return;
+            }
+        }
+
+        let (closing_type, closing) = match self.config.loop_style {
+            LoopStyle::Arrow => {
+                builder.add_block(BlockType::Cycle, header);
+                let (x, y) = builder.position;
+                let closing = match counted {
+                    Some(range) => format!("{x}:{y}:{}", range.step),
+                    None => format!("{x}:{y}"),
+                };
+                (BlockType::End, closing)
+            }
+            LoopStyle::Boundary => {
+                let label = builder.next_loop_label();
+                let text = if header == "loop" {
+                    label.clone()
+                } else {
+                    format!("{label}: {header}")
+                };
+                builder.add_block(BlockType::LoopStart, text);
+                (BlockType::LoopEnd, label)
+            }
         };
+        builder.enter_scope(BlockScope::Loop(closing_type, closing), 0, 100);
+    }
 
-        builder.add_block(BlockType::Cycle, loop_type);
-        builder.enter_scope(BlockScope::Loop(1), 100, 100);
+    /// Closes the innermost loop: loop ends carry the coordinates of their
+    /// loop block (see `CodeBlock::loop_origin`), closing boundaries repeat
+    /// the opening label.
+    fn exit_loop(&self, builder: &mut DiagramBuilder) {
+        if let Some(BlockScope::Loop(block_type, closing)) = builder.block_stack.last() {
+            let (block_type, closing) = (*block_type, closing.clone());
+            builder.add_block(block_type, closing);
+        }
+        builder.exit_scope();
     }
 
-    fn handle_return(node: &Node, builder: &mut DiagramBuilder) {
-        builder.add_block(BlockType::End, "return");
+    fn handle_return(&self, node: &Node, builder: &mut DiagramBuilder) {
+        self.emit_hidden_steps(node, builder);
+        builder.add_block(BlockType::End, without_awaits(node, node.end_byte(), &builder.source));
         builder.position.1 += 100;
     }
 
-    fn handle_macro(node: &Node, builder: &mut DiagramBuilder) {
-        let text = node.utf8_text(builder.source.as_bytes()).unwrap_or_default();
-        let block_type = if text.contains("print") {
+    fn handle_macro(&self, node: &Node, builder: &mut DiagramBuilder) {
+        if concurrency_macro(node, &builder.source).is_some() {
+            return self.handle_concurrency(node, builder, "");
+        }
+
+        let text = builder.node_text(node);
+        let name = node
+            .child_by_field_name("macro")
+            .map(|n| builder.node_text(&n))
+            .unwrap_or_default();
+        if let Some(target) = input_target(node, &builder.source, &builder.stdin) {
+            builder.add_block(BlockType::Input, format!("Ввод {target}"));
+            builder.position.1 += 100;
+            return;
+        }
+
+        let block_type = if DIVERGING_MACROS.contains(&name.rsplit("::").next().unwrap_or_default()) {
+            BlockType::Terminate
+        } else if text.contains("print") {
             BlockType::Print
         } else {
             BlockType::Action
@@ -184,34 +784,1142 @@ impl AstProcessor {
         builder.position.1 += 100;
     }
 
-    fn handle_match(node: &Node, builder: &mut DiagramBuilder) {
-        let arm_count = node.children(&mut node.walk())
+    /// Draws `select!`/`join!` (possibly wrapped in `?` or bound by `let`,
+    /// which `prefix` carries into the head block) as a branch structure.
+    fn handle_concurrency(&self, node: &Node, builder: &mut DiagramBuilder, prefix: &str) {
+        let Some((invocation, name)) = concurrency_macro(node, &builder.source) else {
+            return;
+        };
+        let title = format!("{prefix}{name}!");
+
+        match name.rsplit("::").next() {
+            Some("join" | "try_join") => self.handle_join(&invocation, builder, title),
+            _ => self.handle_select(&invocation, builder, title),
+        }
+    }
+
+    /// `select!` waits for the first of several futures, so it is drawn as a
+    /// multi-way decision with one branch per arm.
+    fn handle_select(&self, node: &Node, builder: &mut DiagramBuilder, title: String) {
+        let arms = select_arms(node, &builder.source);
+
+        builder.add_block(BlockType::Switch, title);
+        let (x, y) = builder.position;
+        let mut bottom = y + 100;
+        builder.enter_scope(BlockScope::Match, 0, 0);
+
+        for (index, (head, body)) in arms.iter().enumerate() {
+            builder.position = (x + 300 * index as i32, y + 100);
+            // ветку выбирает будущее, которое завершилось первым
+            builder.add_block(BlockType::Case, head.clone());
+            self.process_snippet(body, builder);
+            builder.add_block(BlockType::EndMatchArm, "");
+            bottom = bottom.max(builder.position.1);
+        }
+
+        builder.position = (x, bottom);
+        builder.add_block(BlockType::End, "end match");
+        builder.exit_scope();
+    }
+
+    /// `join!` polls all futures concurrently and continues once every one
+    /// of them is ready, so each future gets its own parallel branch.
+    fn handle_join(&self, node: &Node, builder: &mut DiagramBuilder, title: String) {
+        let futures = macro_arguments(node, &builder.source);
+
+        builder.add_block(BlockType::Parallel, title);
+        let (x, y) = builder.position;
+        builder.enter_scope(BlockScope::Match, 0, 0);
+
+        for (index, future) in futures.iter().enumerate() {
+            builder.position = (x + 300 * index as i32, y + 100);
+            builder.add_block(BlockType::Action, format!("ожидание: {future}"));
+            builder.add_block(BlockType::EndMatchArm, "");
+        }
+
+        builder.position = (x, y + 200);
+        builder.add_block(BlockType::End, "end join");
+        builder.exit_scope();
+    }
+
+    /// Draws an iterator chain as the loop it stands for: the consumer's
+    /// accumulator is initialised before the loop, `filter` becomes a
+    /// decision around the rest of the chain and every other stage an action.
+    /// `binding` is the `let` pattern the result is stored in, if any.
+    fn handle_iterator_chain(&self, chain: IteratorChain, binding: Option<String>, builder: &mut DiagramBuilder) {
+        let Some(consumer) = chain.stages.last() else {
+            return;
+        };
+        let element = chain.stages.iter()
+            .find_map(|stage| stage.element().map(|(param, _)| param))
+            .unwrap_or_else(|| "x".to_string());
+        let accumulator = match consumer.method.as_str() {
+            "fold" => consumer.params.first().cloned().or_else(|| binding.clone()),
+            "any" => Some(binding.clone().unwrap_or_else(|| "found".to_string())),
+            "find" => Some(binding.clone().unwrap_or_else(|| "result".to_string())),
+            method => binding.clone().or_else(|| Some(method.to_string())),
+        }
+        .unwrap_or_else(|| "acc".to_string());
+
+        let initial = match consumer.method.as_str() {
+            "fold" => consumer.arguments.first().map(|init| builder.node_text(init)),
+            "sum" | "count" => Some("0".to_string()),
+            "any" => Some("false".to_string()),
+            "find" => Some("None".to_string()),
+            _ => None,
+        };
+        if let Some(initial) = initial {
+            builder.add_block(BlockType::Preparation, format!("{accumulator} = {initial}"));
+            builder.position.1 += 100;
+        }
+
+        let counted = counted_range(&element, &chain.origin, &builder.source);
+        self.enter_loop(format!("for {element} in {}", chain.source), counted, builder);
+
+        let mut current = element;
+        let mut open_conditions = 0;
+        for stage in &chain.stages {
+            let (body_text, body) = match stage.element() {
+                Some((param, body)) => {
+                    if param != current {
+                        builder.add_block(BlockType::Action, format!("{param} = {current}"));
+                        builder.position.1 += 100;
+                        current = param;
+                    }
+                    (builder.node_text(&body), Some(body))
+                }
+                None => match stage.function_path(&builder.source) {
+                    // функция передана по имени: `.map(double)` — это `x = double(x)`
+                    Some(path) if stage.method == "fold" => (format!("{path}({accumulator}, {current})"), None),
+                    Some(path) => (format!("{path}({current})"), None),
+                    None => {
+                        match stage.method.as_str() {
+                            "sum" => builder.add_block(BlockType::Action, format!("{accumulator} = {accumulator} + {current}")),
+                            "count" => builder.add_block(BlockType::Action, format!("{accumulator} = {accumulator} + 1")),
+                            _ => continue,
+                        }
+                        builder.position.1 += 100;
+                        continue;
+                    }
+                },
+            };
+
+            match stage.method.as_str() {
+                "filter" | "any" | "find" => {
+                    let condition = if stage.method == "find" {
+                        format!("{accumulator}.is_none() && {body_text}")
+                    } else {
+                        body_text
+                    };
+                    let depth = builder.block_stack.iter()
+                        .filter(|s| matches!(s, BlockScope::If(_)))
+                        .count() as i32 + 1;
+                    builder.add_block(BlockType::Condition, condition);
+                    builder.enter_scope(BlockScope::If(depth), 100 * depth, 100);
+                    open_conditions += 1;
+
+                    let found = match stage.method.as_str() {
+                        "any" => Some("true".to_string()),
+                        "find" => Some(format!("Some({current})")),
+                        _ => None,
+                    };
+                    if let Some(found) = found {
+                        builder.add_block(BlockType::Action, format!("{accumulator} = {found}"));
+                        builder.position.1 += 100;
+                    }
+                }
+                "map" => {
+                    builder.add_block(BlockType::Action, format!("{current} = {body_text}"));
+                    builder.position.1 += 100;
+                }
+                "fold" => {
+                    builder.add_block(BlockType::Action, format!("{accumulator} = {body_text}"));
+                    builder.position.1 += 100;
+                }
+                _ => match body {
+                    Some(body) => self.process_body(&body, builder),
+                    None => {
+                        let callee = stage.function_argument()
+                            .and_then(|path| resolve_function(&path, &builder.source, &builder.functions));
+                        match callee {
+                            Some(callee) => builder.add_linked_block(BlockType::Subroutine, body_text, callee),
+                            None => builder.add_block(BlockType::Action, body_text),
+                        }
+                        builder.position.1 += 100;
+                    }
+                },
+            }
+        }
+
+        for _ in 0..open_conditions {
+            builder.add_block(BlockType::End, "end if");
+            builder.exit_scope();
+        }
+        self.exit_loop(builder);
+
+        if let Some(binding) = binding.filter(|binding| *binding != accumulator) {
+            builder.add_block(BlockType::Action, format!("{binding} = {accumulator}"));
+            builder.position.1 += 100;
+        }
+    }
+
+    fn handle_match(&self, node: &Node, builder: &mut DiagramBuilder) {
+        let Some(body) = node.child_by_field_name("body") else {
+            return;
+        };
+        if let Some(value) = node.child_by_field_name("value") {
+            self.emit_hidden_steps(&value, builder);
+        }
+
+        let mut cursor = body.walk();
+        let arms: Vec<Node> = body.children(&mut cursor)
             .filter(|n| n.kind() == "match_arm")
-            .count();
+            .collect();
 
-        builder.add_block(BlockType::Condition, "match");
-        builder.enter_scope(BlockScope::Match(300, arm_count), -300, 100);
+        builder.add_block(BlockType::Switch, builder.header_text(node, &body));
+        let (x, y) = builder.position;
+        let mut bottom = y + 100;
+        builder.enter_scope(BlockScope::Match, 0, 0);
+
+        for (index, arm) in arms.iter().enumerate() {
+            builder.position = (x + 300 * index as i32, y + 100);
+            self.process_node(arm, builder);
+            bottom = bottom.max(builder.position.1);
+        }
+
+        builder.position = (x, bottom);
+        builder.add_block(BlockType::End, "end match");
+        builder.exit_scope();
     }
 
-    fn handle_match_arm(node: &Node, builder: &mut DiagramBuilder) {
-        builder.position.0 += 300;
-        builder.add_block(BlockType::Action, "arm");
-        builder.position.1 += 100;
+    fn handle_match_arm(&self, node: &Node, builder: &mut DiagramBuilder) {
+        let pattern = node
+            .child_by_field_name("pattern")
+            .map(|n| builder.node_text(&n))
+            .unwrap_or_default();
+        builder.add_block(BlockType::Case, pattern);
+
+        if let Some(value) = node.child_by_field_name("value") {
+            self.process_body(&value, builder);
+        }
+        builder.add_block(BlockType::EndMatchArm, "");
+    }
+}
+
+/// Whether the comment `node` stands on the line of the statement before it.
+fn is_trailing(node: &Node) -> bool {
+    // line comments end after their newline, so only statements count
+    node.prev_sibling().is_some_and(|prev| {
+        !prev.kind().ends_with("comment") && prev.end_position().row == node.start_position().row
+    })
+}
+
+/// Comment text without the comment syntax; `None` for empty comments and
+/// inner doc comments (`//!`, `/*!`), which describe the whole module.
+fn comment_text(comment: &str) -> Option<String> {
+    if comment.starts_with("//!") || comment.starts_with("/*!") {
+        return None;
+    }
+    let text = if let Some(line) = comment.strip_prefix("//") {
+        line.trim_start_matches('/').trim().to_string()
+    } else {
+        let body = comment.trim_start_matches("/*").trim_start_matches('*');
+        body.trim_end_matches("*/")
+            .lines()
+            .map(|line| line.trim().trim_start_matches('*').trim())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+fn rust_parser() -> Parser {
+    let mut parser = Parser::new();
+    parser.set_language(&tree_sitter_rust::language()).unwrap();
+    parser
+}
+
+fn is_expression(kind: &str) -> bool {
+    kind.ends_with("_expression")
+        || kind.ends_with("_literal")
+        || matches!(kind, "identifier" | "compound_assignment_expr")
+}
+
+/// A statement that does nothing but wait, e.g. `tx.send(v).await?;`.
+fn is_pure_await(node: &Node) -> bool {
+    let mut inner = if node.kind() == "expression_statement" {
+        node.named_child(0)
+    } else {
+        Some(*node)
+    };
+    while let Some(expression) = inner.filter(|n| n.kind() == "try_expression") {
+        inner = expression.named_child(0);
     }
+    inner.is_some_and(|n| n.kind() == "await_expression")
+}
+
+/// Source of `node` up to the byte `end` without the `.await` of the
+/// suspension points that `emit_hidden_steps` draws as separate waits.
+fn without_awaits(node: &Node, end: usize, source: &str) -> String {
+    let mut cuts = Vec::new();
+    await_suffixes(node, &mut cuts);
+    let mut text = String::new();
+    let mut from = node.start_byte();
+    for (start, cut_end) in cuts.into_iter().filter(|&(_, cut_end)| cut_end <= end) {
+        text.push_str(&source[from..start]);
+        from = cut_end;
+    }
+    text.push_str(&source[from..end]);
+    text
+}
+
+/// Byte ranges of the `.await` suffixes in `node`, in source order.
+fn await_suffixes(node: &Node, cuts: &mut Vec<(usize, usize)>) {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if !matches!(child.kind(), "closure_expression" | "async_block") {
+            await_suffixes(&child, cuts);
+        }
+    }
+    if node.kind() == "await_expression" {
+        if let Some(future) = node.named_child(0) {
+            cuts.push((future.end_byte(), node.end_byte()));
+        }
+    }
+}
+
+/// Finds a `select!`/`join!` invocation in `node`, looking through `?` and
+/// `.await` (`try_join!(a, b).await?` in futures 0.1 style code).
+fn concurrency_macro<'tree>(node: &Node<'tree>, source: &str) -> Option<(Node<'tree>, String)> {
+    let mut inner = *node;
+    while matches!(inner.kind(), "try_expression" | "await_expression") {
+        inner = inner.named_child(0)?;
+    }
+    if inner.kind() != "macro_invocation" {
+        return None;
+    }
+
+    let name = inner.child_by_field_name("macro")?.utf8_text(source.as_bytes()).ok()?;
+    match name.rsplit("::").next() {
+        Some("select" | "select_biased" | "join" | "try_join") => Some((inner, name.to_string())),
+        _ => None,
+    }
+}
+
+const DIVERGING_MACROS: [&str; 4] = ["panic", "unreachable", "todo", "unimplemented"];
+const DIVERGING_FUNCTIONS: [&str; 2] = ["exit", "abort"];
+
+/// `std::process::exit(code)` and friends: calls that never return. A bare
+/// `exit(code)` counts only if it is imported from `std::process`, see
+/// `process_exit_imports`.
+fn is_diverging_call(node: &Node, source: &str, exits: &HashSet<String>) -> bool {
+    let call = if node.kind() == "expression_statement" {
+        node.named_child(0)
+    } else {
+        Some(*node)
+    };
+    let Some(function) = call
+        .filter(|n| n.kind() == "call_expression")
+        .and_then(|n| n.child_by_field_name("function"))
+    else {
+        return false;
+    };
+
+    let path = &source[function.byte_range()];
+    exits_process(path) || exits.contains(path)
+}
+
+/// `process::exit`, `std::process::abort` and the like.
+fn exits_process(path: &str) -> bool {
+    path.rsplit_once("::")
+        .is_some_and(|(module, name)| module.ends_with("process") && DIVERGING_FUNCTIONS.contains(&name))
+}
+
+/// Collects the names `use` declarations give to `std::process::exit` and
+/// `abort`: `use std::process::exit;`, `use std::process::{abort, exit as quit};`,
+/// `use std::process::*;`.
+fn process_exit_imports(node: &Node, source: &str, exits: &mut HashSet<String>) {
+    if node.kind() == "use_declaration" {
+        if let Some(argument) = node.child_by_field_name("argument") {
+            imported_exits(&argument, "", source, exits);
+        }
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        process_exit_imports(&child, source, exits);
+    }
+}
+
+fn imported_exits(node: &Node, prefix: &str, source: &str, exits: &mut HashSet<String>) {
+    let text = |node: &Node| &source[node.byte_range()];
+    let join = |path: &str| if prefix.is_empty() { path.to_string() } else { format!("{prefix}::{path}") };
+    match node.kind() {
+        "identifier" | "scoped_identifier" => {
+            let path = join(text(node));
+            if exits_process(&path) {
+                let name = path.rsplit("::").next().unwrap_or_default();
+                exits.insert(name.to_string());
+            }
+        }
+        "use_as_clause" => {
+            let (Some(path), Some(alias)) = (node.child_by_field_name("path"), node.child_by_field_name("alias")) else {
+                return;
+            };
+            if exits_process(&join(text(&path))) {
+                exits.insert(text(&alias).to_string());
+            }
+        }
+        "use_wildcard" => {
+            let module = join(text(node).trim_end_matches('*').trim_end_matches("::"));
+            if module.ends_with("process") {
+                exits.extend(DIVERGING_FUNCTIONS.iter().map(|name| name.to_string()));
+            }
+        }
+        "scoped_use_list" => {
+            let prefix = node.child_by_field_name("path").map_or(prefix.to_string(), |path| join(text(&path)));
+            if let Some(list) = node.child_by_field_name("list") {
+                imported_exits(&list, &prefix, source, exits);
+            }
+        }
+        "use_list" => {
+            let mut cursor = node.walk();
+            for child in node.named_children(&mut cursor) {
+                imported_exits(&child, prefix, source, exits);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `receiver.unwrap()` or `receiver.expect(message)`.
+fn unwrap_call(node: &Node, source: &str) -> Option<(String, Option<String>)> {
+    if node.kind() != "call_expression" {
+        return None;
+    }
+    let function = node.child_by_field_name("function")?;
+    if function.kind() != "field_expression" {
+        return None;
+    }
+
+    let method = &source[function.child_by_field_name("field")?.byte_range()];
+    let receiver = source[function.child_by_field_name("value")?.byte_range()].to_string();
+    match method {
+        "unwrap" => Some((receiver, None)),
+        "expect" => {
+            let arguments = node.child_by_field_name("arguments")?;
+            let message = arguments.named_child(0).map(|n| source[n.byte_range()].to_string());
+            Some((receiver, message))
+        }
+        _ => None,
+    }
+}
+
+/// Names of all functions and methods defined in the analyzed source;
+/// methods are qualified by their type, e.g. `Foo::new`.
+fn defined_functions(node: &Node, source: &str, owner: Option<&str>, functions: &mut HashSet<String>) {
+    if node.kind() == "function_item" {
+        if let Some(name) = node.child_by_field_name("name") {
+            functions.insert(qualified(owner, &source[name.byte_range()]));
+        }
+    }
+    let owner = match node.kind() {
+        "impl_item" | "trait_item" => owner_type(node, source),
+        "function_item" => None,
+        _ => owner.map(str::to_string),
+    };
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        defined_functions(&child, source, owner.as_deref(), functions);
+    }
+}
+
+fn qualified(owner: Option<&str>, name: &str) -> String {
+    match owner {
+        Some(owner) => format!("{owner}::{name}"),
+        None => name.to_string(),
+    }
+}
+
+/// Type an `impl` or `trait` block defines methods for, without generics.
+fn owner_type(node: &Node, source: &str) -> Option<String> {
+    let field = if node.kind() == "trait_item" { "name" } else { "type" };
+    let mut owner = node.child_by_field_name(field)?;
+    if owner.kind() == "generic_type" {
+        owner = owner.child_by_field_name("type")?;
+    }
+    Some(source[owner.byte_range()].to_string())
+}
+
+/// Type of the `impl` or `trait` block `node` is inside of.
+fn enclosing_owner(node: &Node, source: &str) -> Option<String> {
+    let mut parent = node.parent();
+    while let Some(node) = parent {
+        if matches!(node.kind(), "impl_item" | "trait_item") {
+            return owner_type(&node, source);
+        }
+        parent = node.parent();
+    }
+    None
+}
+
+/// The callee of a statement that calls a function defined in the analyzed
+/// source: `helper(x)`, `let y = Self::helper(x)?`, `z = self.helper()`.
+fn local_callee(node: &Node, source: &str, functions: &HashSet<String>) -> Option<String> {
+    let mut call = match node.kind() {
+        "expression_statement" => node.named_child(0)?,
+        "let_declaration" => node.child_by_field_name("value")?,
+        _ => *node,
+    };
+    loop {
+        call = match call.kind() {
+            "assignment_expression" | "compound_assignment_expr" => call.child_by_field_name("right")?,
+            "try_expression" | "await_expression" => call.named_child(0)?,
+            _ => break,
+        };
+    }
+    if call.kind() != "call_expression" {
+        return None;
+    }
+    resolve_function(&call.child_by_field_name("function")?, source, functions)
+}
+
+/// Name of the local function `path` refers to. Only a bare name, `Self::f`,
+/// `self.f` and `Type::f` of a type defined here are resolved: `Vec::new`
+/// is not the `new` of a local type.
+fn resolve_function(path: &Node, source: &str, functions: &HashSet<String>) -> Option<String> {
+    let (owner, name) = match path.kind() {
+        "identifier" => (None, *path),
+        "scoped_identifier" => {
+            let owner = &source[path.child_by_field_name("path")?.byte_range()];
+            let owner = match owner {
+                "Self" => enclosing_owner(path, source)?,
+                _ => owner.to_string(),
+            };
+            (Some(owner), path.child_by_field_name("name")?)
+        }
+        "field_expression" => {
+            let receiver = path.child_by_field_name("value")?;
+            if receiver.kind() != "self" {
+                return None;
+            }
+            (Some(enclosing_owner(path, source)?), path.child_by_field_name("field")?)
+        }
+        _ => return None,
+    };
+    let name = &source[name.byte_range()];
+    functions.contains(&qualified(owner.as_deref(), name)).then(|| name.to_string())
+}
+
+const INPUT_MACROS: [&str; 7] = ["read", "try_read", "scan", "try_scan", "scanf", "scan_fmt", "scanln_fmt"];
+
+/// The variable a statement reads from standard input into, if it does:
+/// `stdin().read_line(&mut s)` reads into `s`, `let n: u32 = read!()` or a
+/// `let` whose value parses a line from stdin reads into `n`.
+fn input_target(node: &Node, source: &str, stdin: &HashSet<String>) -> Option<String> {
+    let read = find_input_read(node, source, stdin)?;
+    if node.kind() == "let_declaration" {
+        return node
+            .child_by_field_name("pattern")
+            .map(|pattern| source[pattern.byte_range()].to_string());
+    }
+
+    let arguments: Vec<String> = match read.kind() {
+        "macro_invocation" => {
+            let mut arguments = macro_arguments(&read, source);
+            if arguments.first().is_some_and(|format| format.starts_with('"')) {
+                arguments.remove(0);
+            }
+            arguments
+        }
+        _ => read
+            .child_by_field_name("arguments")
+            .map(|arguments| {
+                arguments
+                    .named_children(&mut arguments.walk())
+                    .map(|argument| source[argument.byte_range()].to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+
+    let targets: Vec<&str> = arguments.iter()
+        .map(|argument| argument.trim_start_matches("&mut ").trim())
+        .collect();
+    if targets.is_empty() {
+        Some("строки".to_string())
+    } else {
+        Some(targets.join(", "))
+    }
+}
+
+/// Finds a call that reads standard input: `read_line`, `read_to_string` or
+/// `lines` on `stdin()` or a handle made from it, or one of the
+/// text_io/scanf-style macros.
+fn find_input_read<'tree>(node: &Node<'tree>, source: &str, stdin: &HashSet<String>) -> Option<Node<'tree>> {
+    match node.kind() {
+        "closure_expression" | "async_block" => return None,
+        "macro_invocation" => {
+            let name = &source[node.child_by_field_name("macro")?.byte_range()];
+            if INPUT_MACROS.contains(&name.rsplit("::").next().unwrap_or_default()) {
+                return Some(*node);
+            }
+        }
+        "call_expression" => {
+            if let Some(function) = node
+                .child_by_field_name("function")
+                .filter(|f| f.kind() == "field_expression")
+            {
+                let method = function.child_by_field_name("field").map(|f| &source[f.byte_range()]);
+                let receiver = function.child_by_field_name("value");
+                if matches!(method, Some("read_line" | "read_to_string" | "lines"))
+                    && receiver.is_some_and(|r| is_stdin(&r, source, stdin))
+                {
+                    return Some(*node);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.children(&mut cursor).collect();
+    children.iter().find_map(|child| find_input_read(child, source, stdin))
+}
+
+/// Whether `node` is standard input: a `stdin()` call, a handle made from
+/// it (`io::stdin().lock()`, `BufReader::new(stdin())`) or a variable in
+/// `stdin` holding one.
+fn is_stdin(node: &Node, source: &str, stdin: &HashSet<String>) -> bool {
+    match node.kind() {
+        "call_expression" => {
+            let Some(function) = node.child_by_field_name("function") else {
+                return false;
+            };
+            if function.kind() == "field_expression" {
+                return function.child_by_field_name("value").is_some_and(|value| is_stdin(&value, source, stdin));
+            }
+            match source[function.byte_range()].rsplit("::").next() {
+                Some("stdin") => true,
+                Some("new") => node
+                    .child_by_field_name("arguments")
+                    .and_then(|arguments| arguments.named_child(0))
+                    .is_some_and(|argument| is_stdin(&argument, source, stdin)),
+                _ => false,
+            }
+        }
+        "reference_expression" | "parenthesized_expression" | "try_expression" => node
+            .named_child(node.named_child_count().saturating_sub(1))
+            .is_some_and(|inner| is_stdin(&inner, source, stdin)),
+        "identifier" => stdin.contains(&source[node.byte_range()]),
+        _ => false,
+    }
+}
+
+/// Collects the variables a `let` binds to standard input, in source order
+/// so handles made from other handles are found too.
+fn stdin_handles(node: &Node, source: &str, stdin: &mut HashSet<String>) {
+    if node.kind() == "let_declaration" {
+        let pattern = node.child_by_field_name("pattern");
+        let value = node.child_by_field_name("value");
+        if let (Some(pattern), Some(value)) = (pattern, value) {
+            if is_stdin(&value, source, stdin) {
+                let name = source[pattern.byte_range()].trim_start_matches("mut ").trim();
+                stdin.insert(name.to_string());
+            }
+        }
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        stdin_handles(&child, source, stdin);
+    }
+}
+
+const ITERATOR_ADAPTERS: [&str; 2] = ["map", "filter"];
+const ITERATOR_CONSUMERS: [&str; 6] = ["for_each", "fold", "sum", "count", "any", "find"];
+
+/// An iterator chain such as `v.iter().filter(..).sum()`: `source` is the
+/// part that produces the iterator, `stages` the adapters and the consumer.
+struct IteratorChain<'tree> {
+    source: String,
+    origin: Node<'tree>,
+    stages: Vec<ChainStage<'tree>>,
+}
+
+struct ChainStage<'tree> {
+    method: String,
+    arguments: Vec<Node<'tree>>,
+    params: Vec<String>,
+}
+
+impl<'tree> ChainStage<'tree> {
+    fn closure(&self) -> Option<&Node<'tree>> {
+        self.arguments.iter().find(|n| n.kind() == "closure_expression")
+    }
+
+    /// The closure parameter bound to the current element (the last one,
+    /// so `fold(init, |acc, x| ..)` yields `x`) and the closure body.
+    fn element(&self) -> Option<(String, Node<'tree>)> {
+        let body = self.closure()?.child_by_field_name("body")?;
+        Some((self.params.last()?.clone(), body))
+    }
+
+    /// A function passed by path instead of a closure, e.g. `.map(double)`;
+    /// for `fold` it is the second argument.
+    fn function_path(&self, source: &str) -> Option<String> {
+        self.function_argument().map(|argument| source[argument.byte_range()].to_string())
+    }
+
+    fn function_argument(&self) -> Option<Node<'tree>> {
+        let argument = self.arguments.get(usize::from(self.method == "fold"))?;
+        matches!(argument.kind(), "identifier" | "scoped_identifier" | "field_expression").then_some(*argument)
+    }
+}
+
+/// Recognises `source.adapter(..)...consumer(..)` where every stage after
+/// `source` is a known adapter and the last one is a consumer that drives
+/// the iteration. Anything else is left as a plain action.
+fn iterator_chain<'tree>(node: &Node<'tree>, source: &str) -> Option<IteratorChain<'tree>> {
+    let mut stages = Vec::new();
+    let mut current = *node;
+
+    while current.kind() == "call_expression" {
+        let mut function = current.child_by_field_name("function")?;
+        if function.kind() == "generic_function" {
+            function = function.child_by_field_name("function")?;
+        }
+        if function.kind() != "field_expression" {
+            break;
+        }
+
+        let method = function.child_by_field_name("field")?.utf8_text(source.as_bytes()).ok()?;
+        let is_consumer = stages.is_empty() && ITERATOR_CONSUMERS.contains(&method);
+        if !is_consumer && !ITERATOR_ADAPTERS.contains(&method) {
+            break;
+        }
+
+        let arguments_node = current.child_by_field_name("arguments")?;
+        let arguments: Vec<Node> = arguments_node
+            .named_children(&mut arguments_node.walk())
+            .filter(|n| !n.is_extra())
+            .collect();
+        let params = arguments.iter()
+            .find(|n| n.kind() == "closure_expression")
+            .map(|closure| closure_params(closure, source))
+            .unwrap_or_default();
+
+        stages.push(ChainStage {
+            method: method.to_string(),
+            arguments,
+            params,
+        });
+        current = function.child_by_field_name("value")?;
+    }
+
+    let consumer = stages.first()?;
+    if !ITERATOR_CONSUMERS.contains(&consumer.method.as_str()) {
+        return None;
+    }
+    stages.reverse();
+    Some(IteratorChain {
+        source: source[current.byte_range()].to_string(),
+        origin: current,
+        stages,
+    })
+}
+
+/// Init, test and increment of a counter running over an integer range.
+struct CountedRange {
+    init: String,
+    test: String,
+    step: String,
+}
+
+/// Recognises `counter in a..b` and `a..=b`, optionally with `.rev()` or
+/// `.step_by(k)`, as a counted loop.
+fn counted_range(counter: &str, value: &Node, source: &str) -> Option<CountedRange> {
+    let is_name = counter != "_" && counter.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if !is_name {
+        return None;
+    }
+    let text = |node: &Node| source[node.byte_range()].trim().to_string();
+
+    let mut range = *value;
+    let mut reversed = false;
+    let mut stride = String::from("1");
+    if range.kind() == "call_expression" {
+        let function = range.child_by_field_name("function")?;
+        if function.kind() != "field_expression" {
+            return None;
+        }
+        match text(&function.child_by_field_name("field")?).as_str() {
+            "rev" => reversed = true,
+            "step_by" => stride = text(&range.child_by_field_name("arguments")?.named_child(0)?),
+            _ => return None,
+        }
+        range = function.child_by_field_name("value")?;
+    }
+    while range.kind() == "parenthesized_expression" {
+        range = range.named_child(0)?;
+    }
+    if range.kind() != "range_expression" || range.child_count() != 3 {
+        return None;
+    }
+    let inclusive = range.child(1)?.kind() == "..=";
+    let (start, end) = (text(&range.child(0)?), text(&range.child(2)?));
+
+    Some(if reversed {
+        CountedRange {
+            init: if inclusive {
+                format!("{counter} = {end}")
+            } else {
+                format!("{counter} = {end} - 1")
+            },
+            test: format!("{counter} >= {start}"),
+            step: format!("{counter} = {counter} - 1"),
+        }
+    } else {
+        CountedRange {
+            init: format!("{counter} = {start}"),
+            test: format!("{counter} {} {end}", if inclusive { "<=" } else { "<" }),
+            step: format!("{counter} = {counter} + {stride}"),
+        }
+    })
+}
+
+fn closure_params(closure: &Node, source: &str) -> Vec<String> {
+    let Some(parameters) = closure.child_by_field_name("parameters") else {
+        return Vec::new();
+    };
+    let mut cursor = parameters.walk();
+    parameters
+        .named_children(&mut cursor)
+        .map(|param| {
+            let mut pattern = param.child_by_field_name("pattern").unwrap_or(param);
+            // `|&x|` binds the element itself
+            while pattern.kind() == "reference_pattern" {
+                match pattern.named_child(0) {
+                    Some(inner) => pattern = inner,
+                    None => break,
+                }
+            }
+            pattern
+        })
+        .filter_map(|param| param.utf8_text(source.as_bytes()).ok())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Splits the token tree of a macro invocation into its top-level,
+/// comma-separated arguments.
+fn macro_arguments(node: &Node, source: &str) -> Vec<String> {
+    let Some(tokens) = node.named_children(&mut node.walk()).find(|n| n.kind() == "token_tree") else {
+        return Vec::new();
+    };
+
+    let mut arguments = Vec::new();
+    let mut start = tokens.start_byte() + 1;
+    let mut cursor = tokens.walk();
+    for child in tokens.children(&mut cursor) {
+        if child.kind() == "," || child.end_byte() == tokens.end_byte() {
+            let argument = source[start..child.start_byte()].trim();
+            if !argument.is_empty() {
+                arguments.push(argument.to_string());
+            }
+            start = child.end_byte();
+        }
+    }
+    arguments
+}
+
+/// Splits `select! { pattern = future => body, ... }` into `(head, body)`
+/// pairs. The head is `pattern = future` (or `else`), the body is the arm
+/// code without its surrounding braces.
+fn select_arms(node: &Node, source: &str) -> Vec<(String, String)> {
+    let Some(tokens) = node.named_children(&mut node.walk()).find(|n| n.kind() == "token_tree") else {
+        return Vec::new();
+    };
+
+    let mut cursor = tokens.walk();
+    let children: Vec<Node> = tokens.children(&mut cursor).collect();
+    let inner = &children[1..children.len().saturating_sub(1)];
+
+    let mut arms = Vec::new();
+    let mut index = 0;
+    while index < inner.len() {
+        let head_start = inner[index].start_byte();
+        // `biased;` in front of the arms ends with a semicolon instead of `=>`
+        while index < inner.len() && !matches!(inner[index].kind(), "=>" | ";") {
+            index += 1;
+        }
+        if index >= inner.len() || inner[index].kind() == ";" {
+            index += 1;
+            continue;
+        }
+        let head = source[head_start..inner[index].start_byte()].trim().to_string();
+        index += 1;
+
+        let body = match inner.get(index) {
+            Some(block) if block.kind() == "token_tree" && source[block.byte_range()].starts_with('{') => {
+                index += 1;
+                let text = &source[block.byte_range()];
+                text[1..text.len() - 1].to_string()
+            }
+            Some(first) => {
+                let body_start = first.start_byte();
+                let mut body_end = first.end_byte();
+                while index < inner.len() && inner[index].kind() != "," {
+                    body_end = inner[index].end_byte();
+                    index += 1;
+                }
+                source[body_start..body_end].to_string()
+            }
+            None => String::new(),
+        };
+        if inner.get(index).is_some_and(|n| n.kind() == ",") {
+            index += 1;
+        }
+        arms.push((head, body));
+    }
+    arms
 }
 
 pub struct RustAnalyzer;
 
 impl RustAnalyzer {
-    pub fn analyze(source: String) -> Vec<CodeBlock> {
-        let mut parser = Parser::new();
-        parser.set_language(&tree_sitter_rust::language()).unwrap();
+    pub fn analyze(source: String, config: &Config) -> Vec<CodeBlock> {
+        let mut parser = rust_parser();
 
         let tree = parser.parse(&source, None).unwrap();
         let mut builder = DiagramBuilder::new(source);
-        let processor = AstProcessor::new();
+        defined_functions(&tree.root_node(), &builder.source, None, &mut builder.functions);
+        process_exit_imports(&tree.root_node(), &builder.source, &mut builder.exits);
+        stdin_handles(&tree.root_node(), &builder.source, &mut builder.stdin);
+        let processor = AstProcessor::new(config.clone());
 
         processor.process_node(&tree.root_node(), &mut builder);
         builder.blocks
     }
 }
+
+pub trait Language {
+    fn get_name(&self) -> &str;
+    fn analyze_to_vec(&self, source: String, config: &Config) -> Vec<CodeBlock>;
+}
+
+pub struct Rust;
+
+impl Language for Rust {
+    fn get_name(&self) -> &str {
+        "Rust"
+    }
+
+    fn analyze_to_vec(&self, source: String, config: &Config) -> Vec<CodeBlock> {
+        RustAnalyzer::analyze(source, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Type and text of the blocks analysed from `body` wrapped in a function.
+    fn blocks_with(body: &str, config: &Config) -> Vec<(BlockType, String)> {
+        let source = format!("fn f() {{\n{body}\n}}\n");
+        RustAnalyzer::analyze(source, config)
+            .into_iter()
+            .map(|block| (block.r#type, block.text))
+            .collect()
+    }
+
+    fn texts(body: &str) -> Vec<String> {
+        blocks_with(body, &Config::default()).into_iter().map(|(_, text)| text).collect()
+    }
+
+    fn expanded(body: &str) -> Vec<String> {
+        let config = Config { expand_iterators: true, ..Config::default() };
+        blocks_with(body, &config).into_iter().map(|(_, text)| text).collect()
+    }
+
+    #[test]
+    fn await_is_drawn_once_as_a_wait() {
+        let texts = texts("let total = fetch(url).await;");
+        assert_eq!(texts[1..3], ["ожидание: fetch(url)", "let total = fetch(url)"]);
+    }
+
+    #[test]
+    fn iterator_stages_given_by_path_are_called_on_the_element() {
+        let texts = expanded("let total: i32 = v.iter().filter(is_even).map(double).sum();");
+        assert!(texts.contains(&"is_even(x)".to_string()), "{texts:?}");
+        assert!(texts.contains(&"x = double(x)".to_string()), "{texts:?}");
+        assert!(texts.contains(&"total = total + x".to_string()), "{texts:?}");
+    }
+
+    #[test]
+    fn iterator_fold_with_path_accumulates_into_the_binding() {
+        let texts = expanded("let total = v.iter().fold(0, add);");
+        assert_eq!(texts[1], "total = 0");
+        assert!(texts.contains(&"total = add(total, x)".to_string()), "{texts:?}");
+    }
+
+    #[test]
+    fn iterator_reference_patterns_bind_the_element() {
+        let texts = expanded("let n = v.iter().filter(|&&x| x > 0).map(|&y| y * 2).count();");
+        assert!(texts.contains(&"x > 0".to_string()), "{texts:?}");
+        assert!(texts.contains(&"y = x".to_string()), "{texts:?}");
+        assert!(!texts.iter().any(|text| text.contains('&')), "{texts:?}");
+    }
+
+    /// Type of the block drawn for the statement `call` in a file with the
+    /// declarations `uses`.
+    fn call_type(uses: &str, call: &str) -> BlockType {
+        let source = format!("{uses}\nfn main() {{\n    {call};\n}}\n");
+        RustAnalyzer::analyze(source, &Config::default())
+            .into_iter()
+            .find(|block| block.text == call)
+            .map(|block| block.r#type)
+            .unwrap()
+    }
+
+    #[test]
+    fn process_exit_diverges() {
+        assert_eq!(call_type("", "std::process::exit(1)"), BlockType::Terminate);
+        assert_eq!(call_type("use std::process;", "process::abort()"), BlockType::Terminate);
+        assert_eq!(call_type("use std::process::exit;", "exit(0)"), BlockType::Terminate);
+        assert_eq!(call_type("use std::process::{abort, exit as quit};", "quit(2)"), BlockType::Terminate);
+        assert_eq!(call_type("use std::process::*;", "abort()"), BlockType::Terminate);
+    }
+
+    #[test]
+    fn other_exits_do_not_diverge() {
+        assert_eq!(call_type("", "exit(1)"), BlockType::Action);
+        assert_eq!(call_type("use std::process::abort;", "exit(1)"), BlockType::Action);
+        assert_eq!(call_type("use game::exit;", "exit(1)"), BlockType::Action);
+        assert_eq!(call_type("", "window::exit(1)"), BlockType::Action);
+    }
+
+    /// Blocks drawn as calls of local functions, with the function they link to.
+    fn linked_calls(source: &str) -> Vec<(String, String)> {
+        RustAnalyzer::analyze(source.to_string(), &Config::default())
+            .into_iter()
+            .filter(|block| block.r#type == BlockType::Subroutine)
+            .map(|block| (block.text, block.link.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn calls_are_linked_only_to_the_function_they_name() {
+        let source = "struct Foo;\nimpl Foo {\n    fn new() -> Self { Foo }\n    fn run(&self) {\n        \
+                      self.step();\n        Self::step(self);\n    }\n    fn step(&self) {}\n}\n\
+                      fn helper() {}\nfn main() {\n    let s = String::new();\n    let v = Vec::new();\n    \
+                      let foo = Foo::new();\n    helper();\n    foo.step();\n    other::helper();\n}\n";
+        assert_eq!(linked_calls(source), [
+            ("self.step()".to_string(), "step".to_string()),
+            ("Self::step(self)".to_string(), "step".to_string()),
+            ("let foo = Foo::new()".to_string(), "new".to_string()),
+            ("helper()".to_string(), "helper".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn reads_from_stdin_are_input() {
+        let blocks = blocks_with(
+            "let mut s = String::new();\nio::stdin().read_line(&mut s).unwrap();\n\
+             let input = std::io::stdin();\nlet mut handle = input.lock();\nhandle.read_line(&mut t).unwrap();\n\
+             BufReader::new(stdin()).read_to_string(&mut u);",
+            &Config::default(),
+        );
+        let inputs: Vec<&str> = blocks.iter()
+            .filter(|(kind, _)| *kind == BlockType::Input)
+            .map(|(_, text)| text.as_str())
+            .collect();
+        assert_eq!(inputs, ["Ввод s", "Ввод t", "Ввод u"]);
+    }
+
+    #[test]
+    fn other_readers_are_not_input() {
+        let blocks = blocks_with(
+            "stdin_log.read_line(&mut s).unwrap();\nfile.read_line(&mut s).unwrap();",
+            &Config::default(),
+        );
+        assert!(blocks.iter().all(|(kind, _)| *kind != BlockType::Input), "{blocks:?}");
+    }
+
+    #[test]
+    fn trailing_comment_annotates_the_statement_head() {
+        let source = "fn f() {\n    for i in 0..n {\n        step(i);\n    } // обход\n    done();\n}\n";
+        let blocks = RustAnalyzer::analyze(source.to_string(), &Config::default());
+        let comments: Vec<(&str, &str)> = blocks.iter()
+            .filter_map(|block| Some((block.text.as_str(), block.comment.as_deref()?)))
+            .collect();
+        assert_eq!(comments, [("for i in 0..n", "обход")]);
+    }
+
+    #[test]
+    fn comments_before_the_first_step_are_not_grouped_with_declarations() {
+        let source = "fn f() {\n    // исходные данные\n    let a = 1; // счётчик\n    let b = 2;\n    \
+                      // расчёт\n    run(a, b);\n}\n";
+        let config = Config { group_declarations: true, ..Config::default() };
+        let blocks = RustAnalyzer::analyze(source.to_string(), &config);
+        let comments: Vec<(BlockType, &str)> = blocks.iter()
+            .filter_map(|block| Some((block.r#type, block.comment.as_deref()?)))
+            .collect();
+        assert_eq!(comments, [
+            (BlockType::Preparation, "исходные данные\nсчётчик"),
+            (BlockType::Action, "расчёт"),
+        ]);
+    }
+
+    #[test]
+    fn block_tails_without_semicolon_are_drawn() {
+        let texts = texts("if c {\n    x += 1\n} else {\n    y = 2\n}");
+        assert!(texts.contains(&"x += 1".to_string()), "{texts:?}");
+        assert!(texts.contains(&"y = 2".to_string()), "{texts:?}");
+    }
+
+    #[test]
+    fn trailing_comments_after_grouped_declarations_annotate_their_statement() {
+        let source = "fn helper() {}\nfn main() {\n    let a = 1;\n    run(a); // запуск\n    if a > 0 {\n        \
+                      go();\n    } // проверка\n    stop();\n}\n";
+        let config = Config { group_declarations: true, ..Config::default() };
+        let blocks = RustAnalyzer::analyze(source.to_string(), &config);
+        let comments: Vec<(&str, &str)> = blocks.iter()
+            .filter_map(|block| Some((block.text.as_str(), block.comment.as_deref()?)))
+            .collect();
+        assert_eq!(comments, [("run(a)", "запуск"), ("if a > 0", "проверка")]);
+    }
+
+    fn decomposed(body: &str) -> Vec<String> {
+        let config = Config { counted_loops: CountedLoops::Decomposed, ..Config::default() };
+        blocks_with(body, &config).into_iter().map(|(_, text)| text).collect()
+    }
+
+    #[test]
+    fn counted_ranges_are_decomposed() {
+        let texts = decomposed("for i in 0..n {\n    go(i);\n}");
+        assert_eq!(texts[1..3], ["i = 0", "i < n"]);
+        assert!(texts.iter().any(|text| text.ends_with(":i = i + 1")), "{texts:?}");
+
+        let texts = decomposed("for i in (1..=n).rev() {\n    go(i);\n}");
+        assert_eq!(texts[1..3], ["i = n", "i >= 1"]);
+        assert!(texts.iter().any(|text| text.ends_with(":i = i - 1")), "{texts:?}");
+
+        let texts = decomposed("for i in (0..n).step_by(2) {\n    go(i);\n}");
+        assert!(texts.iter().any(|text| text.ends_with(":i = i + 2")), "{texts:?}");
+    }
+
+    #[test]
+    fn other_loops_are_not_counted() {
+        let config = Config { counted_loops: CountedLoops::Decomposed, ..Config::default() };
+        for header in ["for x in xs", "for _ in 0..n", "for (i, x) in v.iter().enumerate()"] {
+            let source = format!("fn f() {{\n{header} {{\n    go(x);\n}}\n}}\n");
+            let blocks = RustAnalyzer::analyze(source, &config);
+            assert_eq!((blocks[1].r#type, blocks[1].text.as_str()), (BlockType::Cycle, header));
+            // у цикла без счётчика нет шага, который можно было бы нарисовать
+            let end = blocks.iter().find(|block| block.loop_origin().is_some()).unwrap();
+            assert_eq!(end.full_caption(), "");
+        }
+    }
+}
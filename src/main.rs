@@ -2,16 +2,57 @@ use anyhow::{Context, Result};
 use inquire::{Select, validator::Validation};
 use std::{env, fs, path::PathBuf};
 
+mod config;
+mod dot;
+mod drakon;
+mod geometry;
+mod graphml;
+mod gost_check;
 mod mk_json_blocks;
 mod lang_vec_stuf;
+mod mermaid;
+mod odg;
+mod paginate;
+mod pdf;
+mod plantuml;
+mod png;
+mod sizing;
+mod structogram;
+mod structure;
+mod svg;
+mod tikz;
+mod wrap;
 
 use crate::{
-    lang_vec_stuf::{Language, Rust},
-    mk_json_blocks::create_json_blocks,
+    config::{Config, Format},
+    dot::render_dot,
+    drakon::render_drakon,
+    gost_check::check_diagram,
+    graphml::render_graphml,
+    lang_vec_stuf::{CodeBlock, Language, Rust},
+    mermaid::render_mermaid,
+    mk_json_blocks::{build_diagram, create_json_blocks},
+    odg::render_odg,
+    paginate::paginate,
+    pdf::render_pdf,
+    plantuml::render_plantuml,
+    png::render_png,
+    sizing::fit_blocks,
+    structogram::render_structogram,
+    svg::render_svg,
+    tikz::render_tikz,
 };
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let (config, args) = Config::from_args(env::args().collect())?;
+    if args.get(1).is_some_and(|mode| mode == "check") {
+        let file_path = match get_argument(&args, 2) {
+            Ok(p) => p,
+            Err(_) => prompt_for_file_path()?,
+        };
+        return check(&PathBuf::from(file_path));
+    }
+
     let lang = match get_argument(&args, 1) {
         Ok(l) => l,
         Err(_) => prompt_for_language()?,
@@ -34,8 +75,13 @@ fn main() -> Result<()> {
         .with_context(|| format!("Failed to read file: {}", path.display()))?
         .replace('\t', "");
 
-    let analyzed_vector = selected_language.analyze_to_vec(source_code);
-    let final_string = create_json_blocks(analyzed_vector);
+    let analyzed_vector = selected_language.analyze_to_vec(source_code, &config);
+    let contents = match config.format {
+        Format::PlantUml => render_plantuml(&analyzed_vector, &config).into_bytes(),
+        Format::Structogram => render_structogram(&analyzed_vector, &config).into_bytes(),
+        Format::Drakon => render_drakon(&analyzed_vector, &config).into_bytes(),
+        _ => export_layout(analyzed_vector, &config)?,
+    };
 
     let output_dir = PathBuf::from("outfiles");
     fs::create_dir_all(&output_dir)
@@ -45,13 +91,51 @@ fn main() -> Result<()> {
         .and_then(|s| s.to_str())
         .context("Invalid file name")?;
 
-    let output_path = output_dir.join(format!("{}.json", output_file_name));
-    fs::write(&output_path, final_string)
+    let output_path = output_dir.join(format!("{}.{}", output_file_name, config.format.extension()));
+    fs::write(&output_path, contents)
         .with_context(|| format!("Failed to write to {}", output_path.display()))?;
 
     Ok(())
 }
 
+/// Lays the analysed blocks out and writes them in one of the formats
+/// drawn from block coordinates.
+fn export_layout(mut analyzed_vector: Vec<CodeBlock>, config: &Config) -> Result<Vec<u8>> {
+    fit_blocks(&mut analyzed_vector, config.wrap_width);
+    if let Some(page) = config.page {
+        analyzed_vector = paginate(analyzed_vector, page, config.margin_px());
+    }
+    let diagram = build_diagram(analyzed_vector, config);
+    Ok(match config.format {
+        Format::Json => create_json_blocks(&diagram).into_bytes(),
+        Format::Svg => render_svg(&diagram, config.background).into_bytes(),
+        Format::Pdf => render_pdf(&diagram, config)?,
+        Format::Png => render_png(&diagram, config)?,
+        Format::Dot => render_dot(&diagram, config).into_bytes(),
+        Format::Mermaid => render_mermaid(&diagram).into_bytes(),
+        Format::Tikz => render_tikz(&diagram, config).into_bytes(),
+        Format::GraphMl => render_graphml(&diagram).into_bytes(),
+        Format::Odg => render_odg(&diagram, config)?,
+        Format::PlantUml | Format::Structogram | Format::Drakon => unreachable!("written from the block structure"),
+    })
+}
+
+fn check(path: &PathBuf) -> Result<()> {
+    let diagram = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let violations = check_diagram(&diagram)
+        .with_context(|| format!("Failed to load diagram {}", path.display()))?;
+
+    for violation in &violations {
+        println!("{violation}");
+    }
+    if !violations.is_empty() {
+        anyhow::bail!("{} нарушений ГОСТ 19.701-90 в {}", violations.len(), path.display());
+    }
+    println!("Нарушений не найдено");
+    Ok(())
+}
+
 fn get_argument(args: &[String], index: usize) -> Result<String, anyhow::Error> {
     args.get(index)
         .cloned()
@@ -80,9 +164,9 @@ fn prompt_for_file_path() -> Result<String, anyhow::Error> {
 fn select_language<'a>(
     lang: &str,
     support_language: &'a [Box<dyn Language>],
-) -> Result<&'a Box<dyn Language>, anyhow::Error> {
+) -> Result<&'a dyn Language, anyhow::Error> {
     if let Some(lang) = support_language.iter().find(|l| l.get_name().eq_ignore_ascii_case(lang)) {
-        return Ok(lang);
+        return Ok(lang.as_ref());
     }
 
     let options: Vec<String> = support_language.iter()
@@ -94,5 +178,6 @@ fn select_language<'a>(
 
     support_language.iter()
         .find(|l| l.get_name() == selected)
+        .map(|l| l.as_ref())
         .ok_or_else(|| anyhow::anyhow!("Selected language is not supported"))
 }
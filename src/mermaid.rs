@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+
+use crate::mk_json_blocks::{function_numbers, FullJson, JsBlock};
+
+/// Writes a diagram as a Mermaid `flowchart TD` with one `subgraph` per
+/// function. Node ids are the function name and the block's number inside
+/// the function, so editing one function keeps the ids of the others.
+pub fn render_mermaid(diagram: &FullJson) -> String {
+    let functions = function_numbers(diagram);
+    let count = functions.iter().max().map_or(0, |last| last + 1);
+
+    let mut names = Vec::with_capacity(count);
+    let mut taken = HashSet::new();
+    for function in 0..count {
+        let first = functions.iter().position(|&f| f == function).unwrap_or_default();
+        let start = &diagram.blocks[first];
+        let mut name = identifier(start.link.as_deref().unwrap_or(&start.full_text))
+            .unwrap_or_else(|| format!("f{function}"));
+        if !taken.insert(name.clone()) {
+            name = format!("{name}_{function}");
+            taken.insert(name.clone());
+        }
+        names.push(name);
+    }
+
+    let mut ids = Vec::with_capacity(diagram.blocks.len());
+    let mut counters = vec![0; count];
+    for &function in &functions {
+        ids.push(format!("{}_{}", names[function], counters[function]));
+        counters[function] += 1;
+    }
+
+    let mut mermaid = String::from("flowchart TD\n");
+    for (function, name) in names.iter().enumerate() {
+        let first = functions.iter().position(|&f| f == function).unwrap_or_default();
+        mermaid.push_str(&format!(
+            "    subgraph fn_{name} [\"{}\"]\n",
+            escape(&diagram.blocks[first].full_text)
+        ));
+        let members = diagram.blocks.iter().enumerate()
+            .filter(|(i, block)| functions[*i] == function && !block.repeats_caption());
+        for (index, block) in members {
+            mermaid.push_str(&format!("        {}\n", node(&ids[index], block)));
+        }
+        mermaid.push_str("    end\n");
+    }
+
+    let mut comments = Vec::new();
+    for arrow in &diagram.arrows {
+        let (Some(from), Some(to)) = (ids.get(arrow.start_index), ids.get(arrow.end_index)) else {
+            continue;
+        };
+        if diagram.blocks[arrow.end_index].repeats_caption() {
+            continue;
+        }
+        let link = if arrow.dashed {
+            comments.push(to.as_str());
+            "-.-"
+        } else {
+            "-->"
+        };
+        let label = if arrow.label.is_empty() {
+            String::new()
+        } else {
+            format!("|\"{}\"|", escape(&arrow.label))
+        };
+        mermaid.push_str(&format!("    {from} {link}{label} {to}\n"));
+    }
+    if !comments.is_empty() {
+        mermaid.push_str("    classDef comment fill:none,stroke-dasharray:4 3\n");
+        mermaid.push_str(&format!("    class {} comment\n", comments.join(",")));
+    }
+    mermaid
+}
+
+fn node(id: &str, block: &JsBlock) -> String {
+    let text = escape(&block.full_text);
+    match block.r#type.as_str() {
+        "Условие" => format!("{id}{{\"{text}\"}}"),
+        "Ввод / вывод" => format!("{id}[/\"{text}\"/]"),
+        "Начало / конец" => format!("{id}([\"{text}\"])"),
+        "Цикл for" | "Подготовка" => format!("{id}{{{{\"{text}\"}}}}"),
+        "Предопределённый процесс" => format!("{id}[[\"{text}\"]]"),
+        "Начало цикла" => format!("{id}[/\"{text}\"\\]"),
+        "Конец цикла" => format!("{id}[\\\"{text}\"/]"),
+        "Соединитель" => format!("{id}((\"{text}\"))"),
+        "Межстраничный соединитель" => format!("{id}>\"{text}\"]"),
+        _ => format!("{id}[\"{text}\"]"),
+    }
+}
+
+/// Function name usable in an id: ASCII letters, digits and `_` only.
+fn identifier(name: &str) -> Option<String> {
+    let name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let name = name.trim_matches('_');
+    (!name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit())).then(|| name.to_string())
+}
+
+/// Label text inside double quotes: quotes and markup characters become
+/// Mermaid entity codes, line breaks become `<br>`.
+fn escape(text: &str) -> String {
+    text.replace('#', "#35;")
+        .replace('"', "#quot;")
+        .replace('&', "#38;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('\n', "<br>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::lang_vec_stuf::{Language, Rust};
+    use crate::mk_json_blocks::build_diagram;
+    use crate::sizing::fit_blocks;
+
+    #[test]
+    fn nodes_carry_full_captions_and_function_ids() {
+        let statement = "let answer = compute(total, values.len(), limit * 2, other_value)";
+        let source = format!("fn main() {{\n    {statement};\n}}\n");
+        let config = Config::default();
+        let mut blocks = Rust.analyze_to_vec(source, &config);
+        fit_blocks(&mut blocks, config.wrap_width);
+        let mermaid = render_mermaid(&build_diagram(blocks, &config));
+        assert!(mermaid.contains(&format!("main_1[\"{statement}\"]")), "{mermaid}");
+        assert!(mermaid.contains("subgraph fn_main [\"Начало\"]"), "{mermaid}");
+        assert!(!mermaid.contains("-.-"), "{mermaid}");
+    }
+}
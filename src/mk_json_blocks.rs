@@ -1,33 +1,60 @@
-use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::to_string_pretty;
 
-use crate::lang_vec_stuf::{BlockType, CodeBlock};
+use crate::config::Config;
+use crate::lang_vec_stuf::{loop_label, BlockType, CodeBlock, Jump, Span};
+use crate::sizing::{comment_size, GAP};
+use crate::wrap::is_overlong;
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
-struct FullJson {
-    blocks: Vec<JsBlock>,
-    arrows: Vec<Arrow>,
-    x0: i32,
-    y0: i32,
+pub(crate) struct FullJson {
+    pub(crate) blocks: Vec<JsBlock>,
+    pub(crate) arrows: Vec<Arrow>,
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) x0: i32,
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) y0: i32,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
-struct JsBlock {
-    x: i32,
-    y: i32,
-    text: String,
-    width: i32,
-    height: i32,
-    r#type: String,
-    is_menu_block: bool,
-    font_size: i32,
-    text_height: i32,
-    is_bold: bool,
-    is_italic: bool,
-    text_align: String,
-    labels_position: i32,
+pub(crate) struct JsBlock {
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) x: i32,
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) y: i32,
+    pub(crate) text: String,
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) width: i32,
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) height: i32,
+    pub(crate) r#type: String,
+    pub(crate) is_menu_block: bool,
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) font_size: i32,
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) text_height: i32,
+    pub(crate) is_bold: bool,
+    pub(crate) is_italic: bool,
+    pub(crate) text_align: String,
+    /// Where the editor writes «да» and «нет» on a decision; missing in
+    /// files of other editors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) labels_position: Option<i32>,
+    /// Source span of the block, for exporters that can carry it.
+    #[serde(skip)]
+    pub(crate) source: Option<Span>,
+    /// Function the block links to, see `CodeBlock::link`.
+    #[serde(skip)]
+    pub(crate) link: Option<String>,
+    /// Caption before wrapping and cutting, for exporters that lay text out
+    /// themselves. Empty for a comment that only repeats an overlong caption.
+    #[serde(skip)]
+    pub(crate) full_text: String,
 }
 
 impl JsBlock {
@@ -45,26 +72,54 @@ impl JsBlock {
             is_bold: false,
             is_italic: false,
             text_align: String::new(),
-            labels_position: 1,
+            labels_position: Some(YES_ON_RIGHT),
+            source: None,
+            link: None,
+            full_text: String::new(),
         }
     }
+
+    /// Comment written only because the caption of its block did not fit,
+    /// left out by the exporters that write captions in full.
+    pub(crate) fn repeats_caption(&self) -> bool {
+        self.r#type == "Комментарий" && self.full_text.is_empty()
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
-struct Node {
-    x: i32,
-    y: i32,
+pub(crate) struct Node {
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) x: i32,
+    #[serde(deserialize_with = "rounded")]
+    pub(crate) y: i32,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
-struct Arrow {
-    start_index: usize,
-    end_index: usize,
-    start_connector_index: usize,
-    end_connector_index: usize,
-    nodes: Vec<Node>,
-    counts: Vec<usize>,
+pub(crate) struct Arrow {
+    pub(crate) start_index: usize,
+    pub(crate) end_index: usize,
+    pub(crate) start_connector_index: usize,
+    pub(crate) end_connector_index: usize,
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) counts: Vec<usize>,
+    /// Branch label: "да"/"нет" on decisions, the pattern on match arms.
+    /// Not part of the format: the editor labels decisions itself, see
+    /// `YES_ON_RIGHT`.
+    #[serde(skip)]
+    pub(crate) label: String,
+    /// Dashed line of a comment annotation.
+    #[serde(default, skip_serializing_if = "is_solid")]
+    pub(crate) dashed: bool,
+}
+
+/// The editor saves positions after dragging as fractions, e.g. `490.5`.
+fn rounded<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    f64::deserialize(deserializer).map(|value| value.round() as i32)
+}
+
+fn is_solid(dashed: &bool) -> bool {
+    !dashed
 }
 
 impl Arrow {
@@ -76,20 +131,51 @@ impl Arrow {
             end_connector_index: 0,
             nodes: Vec::<Node>::new(),
             counts: vec![1, 1, 1],
+            label: String::new(),
+            dashed: false,
         }
     }
 }
 
-pub fn create_json_blocks(mut analyzed_vector: Vec<LocalVecBlock>) -> String {
+/// programforyou writes «да» and «нет» next to the side connectors of a
+/// decision itself: `labelsPosition` 1 puts «да» on the right and «нет» on
+/// the left, 0 the other way round.
+pub(crate) const YES_ON_RIGHT: i32 = 1;
+pub(crate) const YES_ON_LEFT: i32 = 0;
+
+/// A decision (or parallel fork) whose branches are still being laid out.
+/// `tails` collects the arrows leaving finished branches until they merge.
+struct Branching {
+    head: usize,
+    tails: Vec<Arrow>,
+}
+
+/// A loop whose body is being laid out, with the `break` and `continue`
+/// blocks that leave it.
+struct OpenLoop {
+    head: usize,
+    label: Option<String>,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// programforyou.ru file of a laid out diagram.
+pub fn create_json_blocks(diagram: &FullJson) -> String {
+    to_string_pretty(diagram).unwrap()
+}
+
+/// Turns analysed blocks into diagram blocks joined by arrows; every
+/// exporter draws from this model.
+pub(crate) fn build_diagram(analyzed_vector: Vec<CodeBlock>, config: &Config) -> FullJson {
+    let labels = &config.branch_labels;
     let mut cycle_acum = Vec::<usize>::new(); //indexing cycle index
     let mut x_min_max_acum = [0, 0]; //max min x for correct arrow adding for cycle
     let mut is_cycle = 0;
-    //let mut is_match = false;
-    let mut else_arrow_iter = 0;
-    let mut last_condition_index = Vec::new();
-    let mut match_arm = Vec::<usize>::new();
-    let mut look_for_cond_xy = Vec::new();
-    //let mut nesting_if_else_iter = Vec::new();
+    let mut branchings = Vec::<Branching>::new(); //open if/match/join blocks
+    let mut pending = Vec::<Arrow>::new(); //arrows waiting for the next block
+    let mut open_connectors = HashSet::<String>::new(); //connectors waiting for their pair
+    let mut comments = Vec::<(usize, JsBlock)>::new(); //annotations, added after all blocks
+    let mut loops = Vec::<OpenLoop>::new(); //loops whose body is being laid out
 
     let mut local_full_blocks = FullJson {
         blocks: Vec::<JsBlock>::new(),
@@ -98,117 +184,105 @@ pub fn create_json_blocks(mut analyzed_vector: Vec<LocalVecBlock>) -> String {
         y0: 0,
     };
 
+    let next_tops = next_block_tops(&analyzed_vector);
     let mut iterator = 0;
-    let mut sluzba = 0;
-    for i in &mut analyzed_vector {
-        println!("thish block ready for processing {i:?}");
-        let local_text = text_analyzer(i);
+    for (index, i) in analyzed_vector.iter().enumerate() {
         let mut local_block = JsBlock::new(i.x, i.y);
+        (local_block.width, local_block.height) = i.size;
+        local_block.text = i.caption(config.wrap_width);
+        local_block.source = i.span;
+        local_block.link.clone_from(&i.link);
+        local_block.full_text = i.full_caption().to_string();
+        let half_width = i.size.0 / 2;
         let mut local_arrow = Arrow::new(iterator);
-        println!("coords: x={}; y={}", i.x, i.y);
+        let mut has_successor = true;
+        let mut breaks = Vec::new(); //arrows leaving a loop by break, for the block after it
 
         match i.r#type {
             BlockType::Start => {
-                block_start(
-                    &mut x_min_max_acum,
-                    &mut local_full_blocks,
-                    &mut local_block,
-                    i,
-                    iterator,
-                );
+                block_start(&mut x_min_max_acum, &mut local_block);
+                pending.clear();
             }
-            BlockType::Condition => {
+            BlockType::Condition | BlockType::Switch | BlockType::Parallel => {
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
                 block_condition(
                     &mut x_min_max_acum,
                     &mut local_block,
                     &mut local_arrow,
                     i,
-                    &mut look_for_cond_xy,
-                    &mut last_condition_index,
+                    &mut branchings,
                     iterator,
-                    &mut sluzba,
                 );
+                if i.r#type == BlockType::Condition {
+                    local_arrow.label.clone_from(&labels.yes);
+                }
             }
             BlockType::Action => {
-                check_x(is_cycle, i.x, &mut x_min_max_acum);
-                //println!("found {} in vec {} {}", i.text, i.y, i.x);
-                local_block.text = if local_text.is_empty() {
-                    i.text.clone()
-                } else {
-                    local_text
-                };
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
+                if let Some((jump, label)) = i.loop_jump() {
+                    // break и continue уходят из тела цикла, а не к следующему блоку
+                    let target = loops.iter_mut().rev().find(|l| label.is_none() || l.label.as_deref() == label);
+                    if let Some(open) = target {
+                        match jump {
+                            Jump::Break => open.breaks.push(iterator),
+                            Jump::Continue => open.continues.push(iterator),
+                        }
+                        has_successor = false;
+                    }
+                }
+            }
+            BlockType::Else => {
+                // ветка "да" закончилась, дальше идёт ветка "нет"
+                let branching = branchings.last_mut().expect("else without condition");
+                branching.tails.append(&mut pending);
+                pending.push(labeled(branch_arrow(branching.head, 3), &labels.no));
+                continue;
+            }
+            BlockType::Case => {
+                let branching = branchings.last().expect("match arm without match");
+                for arrow in pending.iter_mut().filter(|a| a.start_index == branching.head) {
+                    arrow.label.clone_from(&i.text);
+                }
+                continue;
             }
             BlockType::EndMatchArm => {
-                match_arm.push(iterator - 1);
-                println!("push match arm");
+                let branching = branchings.last_mut().expect("match arm without match");
+                branching.tails.append(&mut pending);
+                pending.push(branch_arrow(branching.head, 2));
                 continue;
             }
             BlockType::End => {
-                if i.text == "end if" {
-                    println!("pop look_for");
-                    let (_, _, to_iterator) =
-                        look_for_cond_xy.pop().expect("look_for_cond_xy is empty");
-                    //.unwrap();
-                    local_arrow.start_index = to_iterator;
-                    local_arrow.end_index = iterator;
-                    local_arrow.start_connector_index = 3;
-                    local_arrow.end_connector_index = 0;
-                    local_arrow.nodes = Vec::new();
-                    local_arrow.counts = vec![1, 1, 1, 1, 1];
-                    local_full_blocks.arrows.push(local_arrow);
-                    continue;
-                }
-                if i.text == "end else" {
-                    local_arrow.start_index = else_arrow_iter;
-                    else_arrow_iter = 0;
-                    local_arrow.end_index = iterator;
-                    local_arrow.start_connector_index = 2;
-                    local_arrow.end_connector_index = 0;
-                    local_arrow.nodes = Vec::new();
-                    local_arrow.counts = vec![1, 1, 1, 1, 1];
-                    local_full_blocks.arrows.push(local_arrow);
-                    continue;
-                }
-                if i.text.contains("match") {
-                    //drop(local_arrow);
-                    for i in match_arm.iter() {
-                        let mut local_arrow_local = local_arrow.clone();
-                        local_arrow_local.start_index = *i;
-                        local_arrow_local.end_index = iterator;
-                        local_arrow_local.start_connector_index = 2;
-                        local_arrow_local.end_connector_index = 0;
-                        local_arrow_local.nodes = Vec::new();
-                        local_arrow_local.counts = vec![1, 1, 1, 1, 1];
-                        local_full_blocks.arrows.push(local_arrow_local);
+                match i.text.as_str() {
+                    "end if" => {
+                        let branching = branchings.pop().expect("end if without condition");
+                        pending.push(labeled(branch_arrow(branching.head, 3), &labels.no));
+                        continue;
                     }
-                    continue;
+                    "end else" => {
+                        let mut branching = branchings.pop().expect("end else without condition");
+                        pending.append(&mut branching.tails);
+                        continue;
+                    }
+                    "end match" | "end join" => {
+                        let branching = branchings.pop().expect("end match without match");
+                        pending = branching.tails;
+                        continue;
+                    }
+                    _ => {}
                 }
                 // Обработка блока "Конец"
-                if i.text.contains(":") {
+                if let Some((to_x, to_y)) = i.loop_origin() {
                     // Получение индекса цикла из стека
-                    let cycle_index = if let Some(index) = cycle_acum.last() {
-                        println!("index: {index}");
-                        index
-                    } else {
-                        println!("index stack is empty");
-                        // Возвращаем значение по умолчанию, если стек пуст
-                        &(0_usize)
-                    };
-
-                    // Разделение текста на части по символу ':'
-                    let parts: Vec<&str> = i.text.split(':').collect();
-                    assert!(parts.len() == 2, "Invalid coordinate format");
-
-                    // Парсинг координат
-                    let to_y = parts[1].parse::<i32>().expect("Invalid y coordinate");
-                    let to_x = parts[0].parse::<i32>().expect("Invalid y coordinate");
+                    let cycle_index = cycle_acum.pop().unwrap_or_default();
+                    check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
+
                     // Обновление состояния цикла и координат
                     is_cycle -= 1;
                     x_min_max_acum[0] -= 10;
                     x_min_max_acum[1] += 10;
 
                     local_block.r#type = String::from("Блок");
-                    local_block.text = String::from("iter++");
+                    let cycle_height = local_full_blocks.blocks[cycle_index].height;
                     add_arrow_to_cycle(
                         &mut local_full_blocks,
                         iterator,
@@ -216,113 +290,357 @@ pub fn create_json_blocks(mut analyzed_vector: Vec<LocalVecBlock>) -> String {
                         &mut x_min_max_acum,
                         to_y,
                         to_x,
-                        cycle_index,
+                        &cycle_index,
                     );
+                    if local_full_blocks.blocks[cycle_index].r#type == "Условие" {
+                        // в решение возврат входит сверху, сливаясь со входом цикла
+                        if let Some(arrow) = local_full_blocks.arrows.last_mut() {
+                            return_from_top(arrow, to_x, to_y - cycle_height / 2);
+                        }
+                    }
+
+                    let open = loops.pop();
+                    if let Some(open) = open.as_ref().filter(|open| !open.continues.is_empty()) {
+                        // continue идёт левее линии выхода из цикла
+                        let lane = right_lane(&local_full_blocks, &pending, &comments, open.head);
+                        local_full_blocks.arrows.extend(continue_arrows(open, &local_full_blocks.blocks, &local_block, iterator, lane));
+                        x_min_max_acum[1] = x_min_max_acum[1].max(lane);
+                    }
 
                     // Обновление стрелки
                     add_arrow_from_cycle(
                         &mut local_arrow,
                         i,
-                        cycle_acum.pop().unwrap(),
+                        cycle_index,
                         iterator,
                         &mut x_min_max_acum,
-                        to_y,
-                        to_x,
+                        &local_full_blocks.blocks[cycle_index],
+                        next_tops[index],
                     );
+                    if is_tested_loop(&local_full_blocks.blocks[cycle_index]) {
+                        local_arrow.label.clone_from(&labels.no);
+                    }
+                    if let Some(open) = open {
+                        // выход по break сливается с выходом из цикла
+                        let lane = local_arrow.nodes[1].x;
+                        breaks = break_arrows(&open, &local_full_blocks.blocks, i.x, next_tops[index], lane);
+                    }
                 } else {
                     local_block.r#type = String::from("Начало / конец");
-                    local_block.text = if i.text.is_empty() || i.text == "}" {
-                        //local_block.y -= 100;
-                        "Конец".to_string()
-                    } else {
-                        i.text.clone()
-                    };
+                    has_successor = false;
                 }
             }
             BlockType::Print => {
-                println!("text in json block {}, iterator {iterator}", i.text);
-                //rem_unused(&mut local_full_blocks, iterator, &sluzba);
-                check_x(is_cycle, i.x, &mut x_min_max_acum);
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
+                local_block.r#type = String::from("Ввод / вывод");
+            }
+            BlockType::Input => {
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
                 local_block.r#type = String::from("Ввод / вывод");
-                if i.text.is_empty() {
-                    local_block.text = String::from("Вывод строки");
+            }
+            BlockType::LoopStart => {
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
+                local_block.r#type = String::from("Начало цикла");
+                loops.push(open_loop(iterator, i));
+            }
+            BlockType::LoopEnd => {
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
+                local_block.r#type = String::from("Конец цикла");
+                if let Some(open) = loops.pop() {
+                    let mut lane = right_lane(&local_full_blocks, &pending, &comments, open.head);
+                    if !open.continues.is_empty() {
+                        local_full_blocks.arrows.extend(continue_arrows(&open, &local_full_blocks.blocks, &local_block, iterator, lane));
+                        // break идёт своей линией мимо поворота continue
+                        lane += GAP / 2;
+                    }
+                    breaks = break_arrows(&open, &local_full_blocks.blocks, i.x, next_tops[index], lane);
+                }
+            }
+            BlockType::Preparation => {
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
+                local_block.r#type = String::from("Подготовка");
+            }
+            BlockType::Subroutine => {
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
+                local_block.r#type = String::from("Предопределённый процесс");
+            }
+            BlockType::Connector | BlockType::OffPageConnector => {
+                local_block.r#type = if i.r#type == BlockType::Connector {
+                    String::from("Соединитель")
                 } else {
-                    local_block.text = String::from(i.text.as_str());
+                    String::from("Межстраничный соединитель")
+                };
+                // первый из пары обрывает поток, второй его продолжает
+                has_successor = !open_connectors.insert(i.text.clone());
+                if has_successor {
+                    open_connectors.remove(&i.text);
                 }
             }
+            BlockType::Terminate => {
+                local_block.r#type = String::from("Начало / конец");
+                has_successor = false;
+            }
             BlockType::Cycle => {
                 is_cycle += 1;
                 cycle_acum.push(iterator);
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
                 local_block.r#type = String::from("Цикл for");
-                local_block.text.clone_from(&i.text.to_string())
-            }
-            BlockType::Else => {
-                local_full_blocks
-                    .arrows
-                    .remove(local_full_blocks.arrows.len() - 2); //чисто на рандом тыкнул, мб когда нибуль аукнится
-                                                                 // ну я хз, это костыль, но работает
-                if i.text == "continue" {
-                    else_arrow_iter = iterator - 1;
-                    continue;
+                if is_tested_loop(&local_block) {
+                    local_arrow.label.clone_from(&labels.yes);
+                    // «нет» выходит из цикла вправо
+                    local_block.labels_position = Some(YES_ON_LEFT);
                 }
-                println!("pop iterator {:?}", last_condition_index);
-                add_else_arrow(
-                    local_arrow,
-                    &mut local_full_blocks,
-                    iterator,
-                    last_condition_index.pop().unwrap(),
-                );
-                //x_min_max_acum[0] -= 100;
-                continue;
+                loops.push(open_loop(iterator, i));
+            }
+            BlockType::LoopTest => {
+                is_cycle += 1;
+                cycle_acum.push(iterator);
+                check_x(is_cycle, i.x, half_width, &mut x_min_max_acum);
+                local_block.r#type = String::from("Условие");
+                local_arrow.label.clone_from(&labels.yes);
+                local_block.labels_position = Some(YES_ON_LEFT);
+                loops.push(open_loop(iterator, i));
             }
         }
+
+        if let Some(comment) = &i.comment {
+            let mut block = comment_block(&local_block, comment);
+            // fit_blocks puts an overlong caption first in the comment
+            let own = match comment.strip_prefix(i.full_caption()) {
+                Some(rest) if is_overlong(i.full_caption(), config.wrap_width) => rest.trim_start_matches('\n'),
+                _ => comment,
+            };
+            block.full_text = own.to_string();
+            comments.push((iterator, block));
+        }
+
+        for mut arrow in pending.drain(..) {
+            arrow.end_index = iterator;
+            local_full_blocks.arrows.push(arrow);
+        }
         local_full_blocks.blocks.push(local_block);
-        local_full_blocks.arrows.push(local_arrow);
+        if has_successor {
+            pending.push(local_arrow);
+        }
+        pending.append(&mut breaks);
         iterator += 1;
     }
-    local_full_blocks.arrows.pop();
-    //local_full_blocks.arrows.clear();
-    to_string_pretty(&local_full_blocks).unwrap()
+    // комментарии добавляются в конец, чтобы не сбить индексы стрелок
+    for (owner, comment) in comments {
+        let mut arrow = branch_arrow(owner, 1);
+        arrow.end_index = local_full_blocks.blocks.len();
+        arrow.end_connector_index = 3;
+        arrow.dashed = true;
+        local_full_blocks.arrows.push(arrow);
+        local_full_blocks.blocks.push(comment);
+    }
+    local_full_blocks
+}
+
+/// Number of the function each block belongs to, counted in the order the
+/// functions are drawn: blocks joined by arrows or by a pair of connectors
+/// with the same label are in the same function.
+pub(crate) fn function_numbers(diagram: &FullJson) -> Vec<usize> {
+    let mut parent: Vec<usize> = (0..diagram.blocks.len()).collect();
+    fn root(parent: &mut [usize], mut block: usize) -> usize {
+        while parent[block] != block {
+            parent[block] = parent[parent[block]];
+            block = parent[block];
+        }
+        block
+    }
+    fn join(parent: &mut [usize], a: usize, b: usize) {
+        let (a, b) = (root(parent, a), root(parent, b));
+        parent[a.max(b)] = a.min(b);
+    }
+
+    for arrow in &diagram.arrows {
+        if arrow.start_index < parent.len() && arrow.end_index < parent.len() {
+            join(&mut parent, arrow.start_index, arrow.end_index);
+        }
+    }
+    let mut connectors = HashMap::<(&str, &str), usize>::new();
+    for (index, block) in diagram.blocks.iter().enumerate() {
+        if matches!(block.r#type.as_str(), "Соединитель" | "Межстраничный соединитель") {
+            match connectors.entry((&block.r#type, &block.text)) {
+                Entry::Occupied(pair) => join(&mut parent, *pair.get(), index),
+                Entry::Vacant(slot) => {
+                    slot.insert(index);
+                }
+            }
+        }
+    }
+
+    let mut numbers = HashMap::new();
+    (0..parent.len())
+        .map(|block| {
+            let next = numbers.len();
+            *numbers.entry(root(&mut parent, block)).or_insert(next)
+        })
+        .collect()
+}
+
+/// Calls of functions drawn on the diagram: the index of every call block
+/// with the index of the start block of its callee.
+pub(crate) fn cross_links(diagram: &FullJson) -> Vec<(usize, usize)> {
+    let starts: HashMap<&str, usize> = diagram.blocks.iter()
+        .enumerate()
+        .filter(|(_, b)| b.r#type == "Начало / конец")
+        .filter_map(|(index, b)| Some((b.link.as_deref()?, index)))
+        .collect();
+    diagram.blocks.iter()
+        .enumerate()
+        .filter(|(_, b)| b.r#type == "Предопределённый процесс")
+        .filter_map(|(index, b)| Some((index, *starts.get(b.link.as_deref()?)?)))
+        .collect()
+}
+
+fn branch_arrow(head: usize, start_connector_index: usize) -> Arrow {
+    let mut arrow = Arrow::new(head);
+    arrow.start_connector_index = start_connector_index;
+    arrow
+}
+
+fn labeled(mut arrow: Arrow, label: &str) -> Arrow {
+    arrow.label = label.to_string();
+    arrow
+}
+
+fn open_loop(head: usize, block: &CodeBlock) -> OpenLoop {
+    OpenLoop {
+        head,
+        label: loop_label(&block.text).map(str::to_string),
+        breaks: Vec::new(),
+        continues: Vec::new(),
+    }
+}
+
+/// Vertical line to the right of the blocks from `first` on, their comment
+/// annotations and the lines leaving them, along which jumps leave a loop
+/// body.
+fn right_lane(diagram: &FullJson, pending: &[Arrow], comments: &[(usize, JsBlock)], first: usize) -> i32 {
+    let annotations = comments.iter()
+        .filter(|(owner, _)| *owner >= first)
+        .map(|(_, block)| block.x + block.width / 2);
+    let lines = diagram.arrows.iter()
+        .chain(pending)
+        .filter(|arrow| arrow.start_index >= first)
+        .flat_map(|arrow| arrow.nodes.iter().map(|node| node.x));
+    diagram.blocks[first..].iter()
+        .map(|block| block.x + block.width / 2)
+        .chain(annotations)
+        .chain(lines)
+        .max()
+        .unwrap_or_default()
+        + GAP / 2
+}
+
+/// Arrow from the right side of `from` along `lane` into the top of a
+/// block at `x` whose top edge is at `top`.
+fn jump_arrow(from: usize, blocks: &[JsBlock], lane: i32, x: i32, top: i32) -> Arrow {
+    let jump = &blocks[from];
+    let mut arrow = branch_arrow(from, 1);
+    arrow.nodes = vec![
+        Node { x: jump.x + jump.width / 2, y: jump.y },
+        Node { x: lane, y: jump.y },
+        Node { x: lane, y: top - GAP / 2 },
+        Node { x, y: top - GAP / 2 },
+        Node { x, y: top },
+    ];
+    arrow.counts = vec![1, 1, 1, 1, 1];
+    arrow
+}
+
+/// `continue` goes to the end of the loop body, `closing` at `index`.
+fn continue_arrows(open: &OpenLoop, blocks: &[JsBlock], closing: &JsBlock, index: usize, lane: i32) -> Vec<Arrow> {
+    let top = closing.y - closing.height / 2;
+    open.continues.iter()
+        .map(|&from| {
+            let mut arrow = jump_arrow(from, blocks, lane, closing.x, top);
+            arrow.end_index = index;
+            arrow
+        })
+        .collect()
 }
 
-fn check_x(is_cycle: i32, current_x: i32, x_min_max_acum: &mut [i32; 2]) {
-    println!("enter in check funk");
+/// `break` goes where the loop exit goes, to the block after the loop at
+/// `x` whose top edge is at `next_top`; the target is set with the other
+/// arrows waiting for that block.
+fn break_arrows(open: &OpenLoop, blocks: &[JsBlock], x: i32, next_top: i32, lane: i32) -> Vec<Arrow> {
+    open.breaks.iter()
+        .map(|&from| jump_arrow(from, blocks, lane, x, next_top))
+        .collect()
+}
+
+/// `while` loops and decomposed counted loops test a condition on every
+/// pass, so their edges get branch labels; other loop blocks do not.
+fn is_tested_loop(block: &JsBlock) -> bool {
+    block.r#type == "Условие" || block.text.starts_with("while")
+}
+
+/// Reroutes a loop return arrow to enter the loop decision at its top
+/// connector `(to_x, top)` instead of the left side.
+fn return_from_top(arrow: &mut Arrow, to_x: i32, top: i32) {
+    arrow.nodes[2].y = top - GAP / 2;
+    arrow.nodes[3] = Node { x: to_x, y: top - GAP / 2 };
+    arrow.nodes[4] = Node { x: to_x, y: top };
+    arrow.end_connector_index = 0;
+}
+
+/// Top edge of the block drawn after each block, where arrows leaving a
+/// loop come in.
+fn next_block_tops(blocks: &[CodeBlock]) -> Vec<i32> {
+    let mut tops = vec![0; blocks.len()];
+    let mut next_top = None;
+    for (index, block) in blocks.iter().enumerate().rev() {
+        tops[index] = next_top.unwrap_or(block.y + block.size.1 / 2 + GAP);
+        if !block.is_marker() {
+            next_top = Some(block.y - block.size.1 / 2);
+        }
+    }
+    tops
+}
+
+/// GOST "комментарий": an open bracket to the right of `owner`, joined
+/// to it with a dashed line.
+fn comment_block(owner: &JsBlock, text: &str) -> JsBlock {
+    let (width, height) = comment_size(text);
+    let mut block = JsBlock::new(owner.x + owner.width / 2 + GAP + width / 2, owner.y);
+    block.r#type = String::from("Комментарий");
+    block.text = text.to_string();
+    (block.width, block.height) = (width, height);
+    block.text_align = String::from("left");
+    block
+}
+
+fn check_x(is_cycle: i32, current_x: i32, half_width: i32, x_min_max_acum: &mut [i32; 2]) {
     if is_cycle > 0 {
-        println!("{is_cycle}");
-        if current_x < x_min_max_acum[0] {
-            x_min_max_acum[0] = current_x - 60;
-            println!("not more");
+        if current_x - half_width < x_min_max_acum[0] {
+            x_min_max_acum[0] = current_x - half_width;
         }
-        if current_x > x_min_max_acum[1] {
-            x_min_max_acum[1] = current_x + 60;
-            println!("more");
+        if current_x + half_width > x_min_max_acum[1] {
+            x_min_max_acum[1] = current_x + half_width;
         }
     }
-    println!("after check {x_min_max_acum:?}");
 }
 
 //arrows hendlers
 fn add_arrow_from_cycle(
     local_arrow: &mut Arrow,
-    current: &LocalVecBlock,
+    current: &CodeBlock,
     cycle_acum: usize,
     iterator: usize,
     x_min_max_acum: &mut [i32; 2],
-    to_y: i32,
-    to_x: i32,
+    cycle: &JsBlock,
+    next_top: i32,
 ) {
-    println!("arrow after cycle\n");
-    println!(
-        "metirial:\nx_m_m_a == {x_min_max_acum:?}\nall current: x == {}, y == {}\nto: x == {to_x}; y == {to_y}",
-        current.x, current.y
-    );
-    println!();
+    let (to_x, to_y, cycle_half_width) = (cycle.x, cycle.y, cycle.width / 2);
     // из цикла в блок после }
     // ни при каких обстоятельствах не трогать!!!!!!!!!!!!!!!!!
     let value = vec![
         Node {
             //x: x_min_max_acum[1],
-            x: to_x + 60,
+            x: to_x + cycle_half_width,
             y: to_y,
         },
         Node {
@@ -332,15 +650,15 @@ fn add_arrow_from_cycle(
         },
         Node {
             x: x_min_max_acum[1] + 10,
-            y: current.y + 50,
+            y: next_top - GAP / 2,
         },
         Node {
             x: current.x,
-            y: current.y + 50,
+            y: next_top - GAP / 2,
         },
         Node {
             x: current.x,
-            y: current.y + 70,
+            y: next_top,
         },
     ];
     local_arrow.start_index = cycle_acum;
@@ -355,27 +673,24 @@ fn add_arrow_from_cycle(
 fn add_arrow_to_cycle(
     local_full_blocks: &mut FullJson,
     iterator: usize,
-    current: &LocalVecBlock,
+    current: &CodeBlock,
     x_min_max_acum: &mut [i32; 2],
     to_y: i32,
     to_x: i32,
     cycle_index: &usize,
 ) {
-    println!(
-        "\nadd arrow to cycle\nmetirial:\nx_m_m_a == {x_min_max_acum:?}\nall current: x == {}, y == {}\nto: x == {to_x}; y == {to_y}",
-        current.x, current.y
-    );
+    let cycle_half_width = local_full_blocks.blocks[*cycle_index].width / 2;
     // из крайнего блока цикла в цикл
     // ни при каких обстоятельствах не трогать!!!!!!!!!!!!!!!!!
     let value = vec![
         Node {
             // связь стрелка блок
-            x: current.x - 60,
+            x: current.x - current.size.0 / 2,
             y: current.y,
         },
         Node {
             // угол
-            x: current.x - 90,
+            x: current.x - current.size.0 / 2 - 30,
             y: current.y,
         },
         Node {
@@ -385,11 +700,11 @@ fn add_arrow_to_cycle(
             y: to_y,
         },
         Node {
-            x: to_x - 80,
+            x: to_x - cycle_half_width - 20,
             y: to_y,
         },
         Node {
-            x: to_x - 60,
+            x: to_x - cycle_half_width,
             y: to_y,
         },
     ];
@@ -401,89 +716,46 @@ fn add_arrow_to_cycle(
         end_connector_index: 3,
         nodes: (value),
         counts: vec![1, 1, 1, 1, 1],
+        label: String::new(),
+        dashed: false,
     };
     local_full_blocks.arrows.push(local_arrow_local);
 }
 fn _add_standart_arrow() {}
-fn add_else_arrow(
-    mut local_arrow: Arrow,
-    local_full_blocks: &mut FullJson,
-    iterator: usize,
-    last_index: usize,
-) {
-    local_arrow.start_index = last_index;
-    local_arrow.start_connector_index = 3;
-    local_arrow.end_index = iterator;
-    local_arrow.end_connector_index = 0;
-    local_full_blocks.arrows.push(local_arrow);
-}
 
 //blocks hendlers
-fn block_start(
-    x_min_max_acum: &mut [i32; 2],
-    local_full_blocks: &mut FullJson,
-    local_block: &mut JsBlock,
-    i: &mut LocalVecBlock,
-    iterator: usize,
-) {
-    *x_min_max_acum = [0, 0];
-    //println!("found start {} in vec {} {}", i.text, i.x, i.y);
-    local_block.text.clone_from(&i.text);
+fn block_start(x_min_max_acum: &mut [i32; 2], local_block: &mut JsBlock) {
+    *x_min_max_acum = [local_block.x, local_block.x];
     local_block.r#type = String::from("Начало / конец");
-    local_full_blocks.arrows.pop();
-    //Костыль!! надо найти место, где эта стрелка вылазит
-    if let Some(last_arrow) = local_full_blocks.arrows.last() {
-        if last_arrow.end_index == iterator {
-            local_full_blocks.arrows.pop();
-        }
-    }
 }
 
 fn block_condition(
     x_min_max_acum: &mut [i32; 2],
     local_block: &mut JsBlock,
     local_arrow: &mut Arrow,
-    i: &mut LocalVecBlock,
-    look_for_cond_xy: &mut Vec<(i32, i32, usize)>,
-    last_condition_index: &mut Vec<usize>,
+    i: &CodeBlock,
+    branchings: &mut Vec<Branching>,
     iterator: usize,
-    //is_match: bool
-    sluzba: &mut usize,
 ) {
-    /*local_full_blocks.arrows.retain(|arrow| {
-        !(arrow.end_index == iterator
-            && !arrow.nodes.is_empty()
-            && i.y < arrow.nodes[0].y)
-    });*/
-    *sluzba = iterator;
     x_min_max_acum[0] -= 10;
-    last_condition_index.push(iterator);
-    println!("push iterator {last_condition_index:?}");
-    //println!("found Condition {} in vec {} {}", i.text, i.x, i.y);
-    local_block.text.clone_from(&i.text);
-    local_block.r#type = String::from("Условие");
-    if i.text.contains("match") {
-        local_arrow.start_connector_index = 2;
-        //is_match = true;
-    } else {
-        local_arrow.start_connector_index = 1;
-        println!("push look_for {}", i.text);
-        look_for_cond_xy.push((i.x, i.y, iterator));
-    }
-}
-
-fn text_analyzer(i: &mut LocalVecBlock) -> String {
-    i.text = i.text.replace("\t", "");
-    let mut local_text = String::new();
-    if i.text.len() > 1 {
-        let mid = i.text.chars().count() / 2;
-        let first_half: String = i.text.chars().take(mid).collect();
-        let second_half: String = i.text.chars().skip(mid).collect();
-        local_text = format!("{}\n{}", first_half, second_half);
-    } else {
-        local_text = i.text.clone();
+    branchings.push(Branching {
+        head: iterator,
+        tails: Vec::new(),
+    });
+    match i.r#type {
+        BlockType::Condition => {
+            local_block.r#type = String::from("Условие");
+            local_arrow.start_connector_index = 1;
+        }
+        BlockType::Parallel => {
+            local_block.r#type = String::from("Блок");
+            local_arrow.start_connector_index = 2;
+        }
+        _ => {
+            local_block.r#type = String::from("Условие");
+            local_arrow.start_connector_index = 2;
+        }
     }
-    local_text
 }
 
 //для чистки стрелок между веток в if else
@@ -501,3 +773,53 @@ fn _rem_unused(local_full_blocks: &mut FullJson, iterator: usize, sluzba: &usize
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang_vec_stuf::{Language, Rust};
+    use crate::sizing::fit_blocks;
+
+    fn diagram(source: &str) -> FullJson {
+        let config = Config::default();
+        let mut blocks = Rust.analyze_to_vec(source.to_string(), &config);
+        fit_blocks(&mut blocks, config.wrap_width);
+        build_diagram(blocks, &config)
+    }
+
+    /// Texts of the blocks the arrows leaving the block `text` lead to.
+    fn successors(diagram: &FullJson, text: &str) -> Vec<String> {
+        let index = diagram.blocks.iter().position(|b| b.text == text).unwrap();
+        diagram.arrows.iter()
+            .filter(|a| a.start_index == index)
+            .map(|a| diagram.blocks[a.end_index].text.clone())
+            .collect()
+    }
+
+    #[test]
+    fn jumps_leave_the_loop_body() {
+        let diagram = diagram(
+            "fn main() {\n    for x in xs {\n        if x == 0 {\n            continue;\n        }\n        \
+             if x > 9 {\n            break;\n        }\n        print(x);\n    }\n    done();\n}\n",
+        );
+        assert_eq!(successors(&diagram, "continue"), [""]);
+        assert_eq!(successors(&diagram, "break"), ["done()"]);
+    }
+
+    #[test]
+    fn branch_labels_are_placed_by_the_editor() {
+        let diagram = diagram("fn main() {\n    if ok {\n        go();\n    }\n    while busy {\n        wait();\n    }\n}\n");
+        let json = create_json_blocks(&diagram);
+        assert!(!json.contains("\"label\""), "{json}");
+
+        // «нет» уходит от решения if влево, а из цикла — вправо
+        let position = |text: &str| diagram.blocks.iter().find(|b| b.text == text).unwrap().labels_position;
+        assert_eq!(position("if ok"), Some(YES_ON_RIGHT));
+        assert_eq!(position("while busy"), Some(YES_ON_LEFT));
+        let no = |text: &str| {
+            let index = diagram.blocks.iter().position(|b| b.text == text).unwrap();
+            diagram.arrows.iter().find(|a| a.start_index == index && a.label == "нет").unwrap().start_connector_index
+        };
+        assert_eq!((no("if ok"), no("while busy")), (3, 1));
+    }
+}
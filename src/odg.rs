@@ -0,0 +1,230 @@
+use std::io::{Cursor, Write};
+
+use anyhow::{Context, Result};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::config::{Config, PX_PER_MM};
+use crate::geometry::{bounds, outline, route};
+use crate::mk_json_blocks::{FullJson, JsBlock, Node};
+use crate::sizing::{FONT_SIZE, PADDING};
+use crate::svg::escape;
+
+const NAMESPACES: &str = "xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\" \
+    xmlns:style=\"urn:oasis:names:tc:opendocument:xmlns:style:1.0\" \
+    xmlns:text=\"urn:oasis:names:tc:opendocument:xmlns:text:1.0\" \
+    xmlns:draw=\"urn:oasis:names:tc:opendocument:xmlns:drawing:1.0\" \
+    xmlns:fo=\"urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0\" \
+    xmlns:svg=\"urn:oasis:names:tc:opendocument:xmlns:svg-compatible:1.0\" \
+    office:version=\"1.2\"";
+
+const MANIFEST: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+    <manifest:manifest xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\" manifest:version=\"1.2\">\n \
+    <manifest:file-entry manifest:full-path=\"/\" manifest:version=\"1.2\" \
+    manifest:media-type=\"application/vnd.oasis.opendocument.graphics\"/>\n \
+    <manifest:file-entry manifest:full-path=\"content.xml\" manifest:media-type=\"text/xml\"/>\n \
+    <manifest:file-entry manifest:full-path=\"styles.xml\" manifest:media-type=\"text/xml\"/>\n\
+    </manifest:manifest>\n";
+
+/// Enhanced path coordinates per diagram unit, so rounded outlines keep
+/// their shape.
+const PATH_SCALE: f32 = 10.0;
+
+/// Writes a diagram as a LibreOffice Draw document: every block is a
+/// custom shape with the outline of its GOST symbol, every arrow a
+/// connector glued to the shapes, so moving a block in Draw drags its
+/// arrows along. Draw routes the connectors itself. The whole diagram is
+/// one page fitted to it with the configured margin.
+pub fn render_odg(diagram: &FullJson, config: &Config) -> Result<Vec<u8>> {
+    let routes: Vec<Vec<Node>> = diagram.arrows.iter().map(|a| route(diagram, a)).collect();
+    let (left, top, right, bottom) = bounds(diagram.blocks.iter(), routes.iter());
+    let margin = config.margin_px();
+    let origin = (left - margin, top - margin);
+    let page = (right - left + 2 * margin, bottom - top + 2 * margin);
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // mimetype должен идти первым и без сжатия
+    zip.start_file("mimetype", SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
+    zip.write_all(b"application/vnd.oasis.opendocument.graphics")?;
+    for (name, contents) in [
+        ("META-INF/manifest.xml", MANIFEST.to_string()),
+        ("styles.xml", styles(page, config)),
+        ("content.xml", content(diagram, &routes, origin)),
+    ] {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(contents.as_bytes())?;
+    }
+    let archive = zip.finish().context("Failed to write ODG")?;
+    Ok(archive.into_inner())
+}
+
+/// Page size and the arrowhead and dash the connector styles refer to.
+fn styles(page: (i32, i32), config: &Config) -> String {
+    let orientation = if page.0 > page.1 { "landscape" } else { "portrait" };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <office:document-styles {NAMESPACES}>\n\
+         <office:styles>\n  \
+         <draw:marker draw:name=\"Arrow\" svg:viewBox=\"0 0 20 30\" svg:d=\"M10 0l-10 30h20z\"/>\n  \
+         <draw:stroke-dash draw:name=\"Dash\" draw:style=\"rect\" draw:dots1=\"1\" draw:dots1-length=\"{}\" draw:distance=\"{}\"/>\n  \
+         <style:default-style style:family=\"graphic\">\n    \
+         <style:text-properties fo:font-family=\"Arial\" fo:font-size=\"{}pt\"/>\n  \
+         </style:default-style>\n\
+         </office:styles>\n\
+         <office:automatic-styles>\n  \
+         <style:page-layout style:name=\"page\">\n    \
+         <style:page-layout-properties fo:page-width=\"{}\" fo:page-height=\"{}\" fo:margin-top=\"{margin}mm\" \
+         fo:margin-bottom=\"{margin}mm\" fo:margin-left=\"{margin}mm\" fo:margin-right=\"{margin}mm\" \
+         style:print-orientation=\"{orientation}\"/>\n  \
+         </style:page-layout>\n\
+         </office:automatic-styles>\n\
+         <office:master-styles>\n  \
+         <style:master-page style:name=\"Default\" style:page-layout-name=\"page\"/>\n\
+         </office:master-styles>\n\
+         </office:document-styles>\n",
+        cm(6.0),
+        cm(4.0),
+        FONT_SIZE as f32 * 0.75,
+        cm(page.0 as f32),
+        cm(page.1 as f32),
+        margin = config.margin,
+    )
+}
+
+fn content(diagram: &FullJson, routes: &[Vec<Node>], origin: (i32, i32)) -> String {
+    let line = cm(1.5);
+    let mut content = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <office:document-content {NAMESPACES}>\n\
+         <office:automatic-styles>\n  \
+         <style:style style:name=\"block\" style:family=\"graphic\">\n    \
+         <style:graphic-properties draw:stroke=\"solid\" svg:stroke-width=\"{line}\" svg:stroke-color=\"#000000\" \
+         draw:fill=\"solid\" draw:fill-color=\"#ffffff\" draw:textarea-horizontal-align=\"center\" \
+         draw:textarea-vertical-align=\"middle\" draw:auto-grow-height=\"false\" fo:padding-top=\"0cm\" \
+         fo:padding-bottom=\"0cm\" fo:padding-left=\"0cm\" fo:padding-right=\"0cm\"/>\n  \
+         </style:style>\n  \
+         <style:style style:name=\"comment\" style:family=\"graphic\" style:parent-style-name=\"block\">\n    \
+         <style:graphic-properties draw:fill=\"none\" draw:textarea-horizontal-align=\"left\" fo:padding-left=\"{}\"/>\n  \
+         </style:style>\n  \
+         <style:style style:name=\"arrow\" style:family=\"graphic\">\n    \
+         <style:graphic-properties draw:stroke=\"solid\" svg:stroke-width=\"{line}\" svg:stroke-color=\"#000000\" \
+         draw:marker-end=\"Arrow\" draw:marker-end-width=\"{}\" draw:fill=\"none\"/>\n  \
+         </style:style>\n  \
+         <style:style style:name=\"comment-line\" style:family=\"graphic\">\n    \
+         <style:graphic-properties draw:stroke=\"dash\" draw:stroke-dash=\"Dash\" svg:stroke-width=\"{line}\" \
+         svg:stroke-color=\"#000000\" draw:fill=\"none\"/>\n  \
+         </style:style>\n  \
+         <style:style style:name=\"centered\" style:family=\"paragraph\">\n    \
+         <style:paragraph-properties fo:text-align=\"center\"/>\n  \
+         </style:style>\n  \
+         <style:style style:name=\"left\" style:family=\"paragraph\">\n    \
+         <style:paragraph-properties fo:text-align=\"start\"/>\n  \
+         </style:style>\n\
+         </office:automatic-styles>\n\
+         <office:body>\n\
+         <office:drawing>\n\
+         <draw:page draw:name=\"Схема\" draw:master-page-name=\"Default\">\n",
+        cm(PADDING),
+        cm(10.0),
+    );
+
+    for (index, block) in diagram.blocks.iter().enumerate() {
+        let (style, paragraph) = if block.r#type == "Комментарий" {
+            ("comment", "left")
+        } else {
+            ("block", "centered")
+        };
+        content.push_str(&format!(
+            "<draw:custom-shape draw:id=\"b{index}\" draw:style-name=\"{style}\" draw:text-style-name=\"{paragraph}\" \
+             svg:x=\"{}\" svg:y=\"{}\" svg:width=\"{}\" svg:height=\"{}\">\n",
+            cm((block.x - block.width / 2 - origin.0) as f32),
+            cm((block.y - block.height / 2 - origin.1) as f32),
+            cm(block.width as f32),
+            cm(block.height as f32)
+        ));
+        paragraphs(&mut content, &block.text, paragraph);
+        content.push_str(&format!(
+            "  <draw:enhanced-geometry svg:viewBox=\"0 0 {} {}\" draw:type=\"{}\" draw:enhanced-path=\"{}\"/>\n\
+             </draw:custom-shape>\n",
+            block.width as f32 * PATH_SCALE,
+            block.height as f32 * PATH_SCALE,
+            shape(block),
+            enhanced_path(block)
+        ));
+    }
+
+    for (arrow, points) in diagram.arrows.iter().zip(routes) {
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            continue;
+        };
+        let style = if arrow.dashed { "comment-line" } else { "arrow" };
+        content.push_str(&format!(
+            "<draw:connector draw:style-name=\"{style}\" draw:text-style-name=\"centered\" draw:type=\"standard\" \
+             draw:start-shape=\"b{}\" draw:start-glue-point=\"{}\" draw:end-shape=\"b{}\" draw:end-glue-point=\"{}\" \
+             svg:x1=\"{}\" svg:y1=\"{}\" svg:x2=\"{}\" svg:y2=\"{}\">\n",
+            arrow.start_index,
+            arrow.start_connector_index,
+            arrow.end_index,
+            arrow.end_connector_index,
+            cm((first.x - origin.0) as f32),
+            cm((first.y - origin.1) as f32),
+            cm((last.x - origin.0) as f32),
+            cm((last.y - origin.1) as f32)
+        ));
+        paragraphs(&mut content, &arrow.label, "centered");
+        content.push_str("</draw:connector>\n");
+    }
+
+    content.push_str("</draw:page>\n</office:drawing>\n</office:body>\n</office:document-content>\n");
+    content
+}
+
+/// One paragraph per line of text.
+fn paragraphs(content: &mut String, text: &str, style: &str) {
+    for line in text.lines() {
+        content.push_str(&format!("  <text:p text:style-name=\"{style}\">{}</text:p>\n", escape(line)));
+    }
+}
+
+/// Preset name of the shape for Draw; the outline itself comes from
+/// `enhanced_path`, since loop limits and the comment bracket have no preset.
+fn shape(block: &JsBlock) -> &'static str {
+    match block.r#type.as_str() {
+        "Условие" => "flowchart-decision",
+        "Ввод / вывод" => "flowchart-data",
+        "Начало / конец" => "flowchart-terminator",
+        "Цикл for" | "Подготовка" => "flowchart-preparation",
+        "Предопределённый процесс" => "flowchart-predefined-process",
+        "Соединитель" => "flowchart-connector",
+        "Межстраничный соединитель" => "flowchart-off-page-connector",
+        "Блок" => "flowchart-process",
+        _ => "non-primitive",
+    }
+}
+
+/// Outline of the block in its own coordinates; open lines are not filled.
+fn enhanced_path(block: &JsBlock) -> String {
+    let (left, top) = ((block.x - block.width / 2) as f32, (block.y - block.height / 2) as f32);
+    let mut path = Vec::new();
+    for figure in outline(block) {
+        for (index, (x, y)) in figure.points.iter().enumerate() {
+            let command = match index {
+                0 => "M ",
+                1 => "L ",
+                _ => "",
+            };
+            path.push(format!(
+                "{command}{} {}",
+                ((x - left) * PATH_SCALE).round(),
+                ((y - top) * PATH_SCALE).round()
+            ));
+        }
+        path.push(String::from(if figure.closed { "Z N" } else { "F N" }));
+    }
+    path.join(" ")
+}
+
+/// Length in centimetres of `px` diagram units.
+fn cm(px: f32) -> String {
+    format!("{:.3}cm", px / PX_PER_MM / 10.0)
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use crate::config::PageSize;
+use crate::lang_vec_stuf::{BlockType, CodeBlock, LABEL_LETTERS};
+use crate::sizing::{comment_size, CONNECTOR_SIZE, GAP, OFF_PAGE_CONNECTOR_SIZE};
+use crate::structure::{diverges, function_end, functions};
+
+/// Gap between pages, which are laid out left to right.
+pub const PAGE_GAP: i32 = 100;
+/// Room on both sides of a column for loop return arrows.
+const LOOP_MARGIN: i32 = 60;
+/// Height reserved for a connector and the space below or above it.
+const CONNECTOR_ROW: i32 = OFF_PAGE_CONNECTOR_SIZE.1 + GAP;
+
+
+/// A run of blocks drawn as one column; `continued` is set when the flow
+/// goes on from the previous column and needs a pair of connectors.
+struct Column {
+    start: usize,
+    end: usize,
+    continued: bool,
+}
+
+/// Splits a long diagram into columns that fit on `page`, places as many
+/// columns side by side on a page as fit and joins the cut flows with
+/// numbered on-page connectors (same page) or lettered off-page connectors.
+/// `margin` is left free on every side of a page.
+pub fn paginate(blocks: Vec<CodeBlock>, page: PageSize, margin: i32) -> Vec<CodeBlock> {
+    if blocks.is_empty() {
+        return blocks;
+    }
+    let (page_width, page_height) = page.dimensions();
+    let columns = split_columns(&blocks, page_height - 2 * margin);
+
+    let mut placements = Vec::with_capacity(columns.len());
+    let (mut page_index, mut used) = (0, 0);
+    for column in &columns {
+        let (min_x, max_x) = x_range(&blocks[column.start..column.end]);
+        let width = max_x - min_x + 2 * LOOP_MARGIN;
+        if used > 0 && used + width > page_width - 2 * margin {
+            page_index += 1;
+            used = 0;
+        }
+        let left = page_index * (page_width + PAGE_GAP) + margin + used;
+        placements.push((page_index, left + LOOP_MARGIN - min_x));
+        used += width;
+    }
+
+    let mut result = Vec::with_capacity(blocks.len() + 2 * columns.len());
+    let mut loops = HashMap::new();
+    let mut connector: Option<(bool, String)> = None;
+    let (mut on_page, mut off_page) = (0, 0);
+    let mut blocks = blocks.into_iter();
+
+    for (index, column) in columns.iter().enumerate() {
+        let (page_index, shift_x) = placements[index];
+        let mut top = margin;
+        let first = blocks.as_slice().first().map_or((0, 0), |b| (b.x, b.y - b.size.1 / 2));
+
+        if let Some((is_off_page, label)) = connector.take() {
+            result.push(connector_block(is_off_page, label, first.0 + shift_x, top));
+            top += CONNECTOR_ROW;
+        }
+        let shift_y = top - first.1;
+
+        let mut bottom = top;
+        for mut block in blocks.by_ref().take(column.end - column.start) {
+            if let Some(origin) = block.loop_origin() {
+                if let Some((x, y)) = loops.get(&origin) {
+                    block.text = match block.loop_step() {
+                        Some(step) => format!("{x}:{y}:{step}"),
+                        None => format!("{x}:{y}"),
+                    };
+                }
+            }
+            let old = (block.x, block.y);
+            block.x += shift_x;
+            block.y += shift_y;
+            if matches!(block.r#type, BlockType::Cycle | BlockType::LoopTest) {
+                loops.insert(old, (block.x, block.y));
+            }
+            if !block.is_marker() {
+                bottom = bottom.max(block.y + block.size.1 / 2);
+            }
+            result.push(block);
+        }
+
+        if !columns.get(index + 1).is_some_and(|next| next.continued) {
+            continue;
+        }
+        let is_off_page = placements[index + 1].0 != page_index;
+        let label = if is_off_page {
+            off_page += 1;
+            letter_label(off_page - 1)
+        } else {
+            on_page += 1;
+            on_page.to_string()
+        };
+        let x = blocks.as_slice().first().map_or(0, |b| b.x) + shift_x;
+        result.push(connector_block(is_off_page, label.clone(), x, bottom + GAP));
+        connector = Some((is_off_page, label));
+    }
+    result
+}
+
+/// Greedily fills columns up to `height`, cutting at the last free place
+/// before the overflow. A structure taller than a page stays whole.
+fn split_columns(blocks: &[CodeBlock], height: i32) -> Vec<Column> {
+    let free = free_places(blocks);
+    let mut columns = Vec::new();
+    let (mut start, mut continued) = (0, false);
+    let mut last_free = None;
+
+    let mut index = 1;
+    while index < blocks.len() {
+        if !blocks[index].is_marker() && column_height(&blocks[start..=index], continued) > height {
+            let here = free[index].then_some(index);
+            if let Some(cut) = last_free.or(here) {
+                columns.push(Column { start, end: cut, continued });
+                continued = blocks[cut].r#type != BlockType::Start && !flow_stops(&blocks[..cut]);
+                start = cut;
+                last_free = None;
+                index = cut + 1;
+                continue;
+            }
+        }
+        if free[index] {
+            last_free = Some(index);
+        }
+        index += 1;
+    }
+    columns.push(Column { start, end: blocks.len(), continued });
+    columns
+}
+
+/// Places right before a block where the flow may be cut: between
+/// top-level steps or functions. Inside a branch or a loop the arrows of
+/// the open structure would have to cross the cut.
+fn free_places(blocks: &[CodeBlock]) -> Vec<bool> {
+    let mut depth = 0;
+    let mut free = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        free.push(depth == 0 && !block.is_marker());
+        match block.r#type {
+            BlockType::Condition | BlockType::Switch | BlockType::Parallel => depth += 1,
+            BlockType::Cycle | BlockType::LoopTest | BlockType::LoopStart => depth += 1,
+            BlockType::LoopEnd => depth -= 1,
+            BlockType::End if block.loop_origin().is_some() || block.is_marker() => depth -= 1,
+            _ => {}
+        }
+    }
+    free
+}
+
+/// Whether nothing follows the last of `blocks`: the function is over,
+/// or its last step returns or terminates the program.
+fn flow_stops(blocks: &[CodeBlock]) -> bool {
+    blocks.last().is_some_and(function_end)
+        || functions(blocks).last().is_some_and(|function| diverges(&function.body))
+}
+
+/// Height of a column holding `blocks`, with room for an incoming
+/// connector if `continued` and always for an outgoing one.
+fn column_height(blocks: &[CodeBlock], continued: bool) -> i32 {
+    let top = blocks[0].y - blocks[0].size.1 / 2;
+    let bottom = blocks.iter()
+        .filter(|b| !b.is_marker())
+        .map(|b| b.y + b.size.1 / 2)
+        .max()
+        .unwrap_or(top);
+    let connectors = if continued { 2 * CONNECTOR_ROW } else { CONNECTOR_ROW };
+    bottom - top + connectors
+}
+
+/// Left and right edge of `blocks`, comment annotations included.
+fn x_range(blocks: &[CodeBlock]) -> (i32, i32) {
+    blocks.iter()
+        .filter(|b| !b.is_marker())
+        .fold((i32::MAX, i32::MIN), |(min, max), b| {
+            let mut right = b.x + b.size.0 / 2;
+            if let Some(comment) = &b.comment {
+                right += GAP + comment_size(comment).0;
+            }
+            (min.min(b.x - b.size.0 / 2), max.max(right))
+        })
+}
+
+fn letter_label(index: usize) -> String {
+    let letter = LABEL_LETTERS[index % LABEL_LETTERS.len()];
+    match index / LABEL_LETTERS.len() {
+        0 => letter.to_string(),
+        round => format!("{letter}{round}"),
+    }
+}
+
+/// Connector whose top edge is at `top`.
+fn connector_block(is_off_page: bool, text: String, x: i32, top: i32) -> CodeBlock {
+    let (r#type, size) = if is_off_page {
+        (BlockType::OffPageConnector, OFF_PAGE_CONNECTOR_SIZE)
+    } else {
+        (BlockType::Connector, CONNECTOR_SIZE)
+    };
+    CodeBlock {
+        r#type,
+        text,
+        x,
+        y: top + size.1 / 2,
+        link: None,
+        comment: None,
+        size,
+        span: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Paper};
+    use crate::lang_vec_stuf::{Language, Rust};
+    use crate::sizing::fit_blocks;
+
+    const A4: PageSize = PageSize { paper: Paper::A4, landscape: false };
+
+    fn paginated(source: &str) -> Vec<CodeBlock> {
+        let config = Config::default();
+        let mut blocks = Rust.analyze_to_vec(source.to_string(), &config);
+        fit_blocks(&mut blocks, config.wrap_width);
+        paginate(blocks, A4, 40)
+    }
+
+    fn is_connector(block: &CodeBlock) -> bool {
+        matches!(block.r#type, BlockType::Connector | BlockType::OffPageConnector)
+    }
+
+    fn lines(count: usize) -> String {
+        (0..count).map(|i| format!("        println!(\"{i}\");\n")).collect()
+    }
+
+    #[test]
+    fn loops_are_not_cut() {
+        let source = format!(
+            "fn main() {{\n{}    for i in 0..n {{\n{}    }}\n{}}}\n",
+            lines(6), lines(12), lines(6),
+        );
+        let blocks = paginated(&source);
+        assert!(blocks.iter().any(is_connector));
+        let mut depth = 0;
+        for block in &blocks {
+            match block.r#type {
+                BlockType::Cycle => depth += 1,
+                BlockType::End if block.loop_origin().is_some() => depth -= 1,
+                _ => assert!(depth == 0 || !is_connector(block), "разрыв внутри цикла"),
+            }
+        }
+    }
+
+    #[test]
+    fn no_connectors_after_exit() {
+        let source = format!(
+            "fn main() {{\n{}    std::process::exit(1);\n{}}}\n",
+            lines(5), lines(3),
+        );
+        let blocks = paginated(&source);
+        let exit = blocks.iter().position(|b| b.r#type == BlockType::Terminate).unwrap();
+        assert!(!blocks.iter().any(is_connector));
+        assert!(blocks[exit + 1].y < blocks[exit].y, "код после выхода не перенесён");
+    }
+}
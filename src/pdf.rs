@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use printpdf::lopdf::{self, dictionary, Object};
+use printpdf::path::{PaintMode, WindingOrder};
+use printpdf::{
+    Color, Greyscale, IndirectFontRef, Line, LineDashPattern, Mm, PdfDocument, PdfLayerReference, Point, Polygon,
+};
+
+use crate::config::{Config, PageSize, PX_PER_MM};
+use crate::geometry::{arrowhead, bounds, caption_lines, label_line, outline, route, Anchor, TextLine};
+use crate::mk_json_blocks::{cross_links, FullJson, JsBlock, Node};
+use crate::paginate::PAGE_GAP;
+use crate::sizing::{text_width, FONT, FONT_SIZE};
+
+/// Points (1/72 in) per diagram unit (1/96 in).
+const PT_PER_PX: f32 = 0.75;
+const LINE_WIDTH: f32 = 1.5;
+
+/// Part of the diagram printed on one page, in diagram units.
+struct Sheet {
+    left: i32,
+    top: i32,
+    width: i32,
+    height: i32,
+    /// Which blocks are drawn on the page; arrows go with their start.
+    blocks: Vec<bool>,
+}
+
+impl Sheet {
+    fn point(&self, x: f32, y: f32) -> Point {
+        Point::new(
+            Mm((x - self.left as f32) / PX_PER_MM),
+            Mm((self.top + self.height) as f32 / PX_PER_MM - y / PX_PER_MM),
+        )
+    }
+}
+
+/// Prints a diagram to PDF with vector shapes and an embedded Cyrillic
+/// font. With `--page` every page of the layout becomes a PDF page of that
+/// size; otherwise each function gets a page fitted to it. Both keep the
+/// configured margin free. Calls of drawn functions link to the page of
+/// the callee.
+pub fn render_pdf(diagram: &FullJson, config: &Config) -> Result<Vec<u8>> {
+    let routes: Vec<Vec<Node>> = diagram.arrows.iter().map(|a| route(diagram, a)).collect();
+    let sheets = match config.page {
+        Some(page) => page_sheets(diagram, page),
+        None => function_sheets(diagram, &routes, config.margin_px()),
+    };
+
+    let first = &sheets[0];
+    let (document, page, layer) = PdfDocument::new(
+        "Блок-схема",
+        Mm(first.width as f32 / PX_PER_MM),
+        Mm(first.height as f32 / PX_PER_MM),
+        "Схема",
+    );
+    let font = document.add_external_font(FONT).context("Failed to embed font")?;
+    for (index, sheet) in sheets.iter().enumerate() {
+        let (page, layer) = if index == 0 {
+            (page, layer)
+        } else {
+            document.add_page(
+                Mm(sheet.width as f32 / PX_PER_MM),
+                Mm(sheet.height as f32 / PX_PER_MM),
+                "Схема",
+            )
+        };
+        let layer = document.get_page(page).get_layer(layer);
+        draw_sheet(&layer, diagram, &routes, sheet, &font);
+    }
+    let pdf = document.save_to_bytes().context("Failed to write PDF")?;
+    add_links(pdf, &page_links(diagram, &sheets))
+}
+
+/// Clickable area of a call block leading to the start of its callee.
+struct PageLink {
+    page: usize,
+    /// Left, bottom, right and top edge in points.
+    rect: [f32; 4],
+    target: usize,
+    /// Top left corner of the callee's start block in points.
+    destination: (f32, f32),
+}
+
+fn page_links(diagram: &FullJson, sheets: &[Sheet]) -> Vec<PageLink> {
+    let page_of = |block: usize| sheets.iter().position(|sheet| sheet.blocks[block]);
+    cross_links(diagram)
+        .into_iter()
+        .filter_map(|(call, start)| {
+            let (page, target) = (page_of(call)?, page_of(start)?);
+            let (call, start) = (&diagram.blocks[call], &diagram.blocks[start]);
+            let corner = |block: &JsBlock, sheet: &Sheet, dx: i32, dy: i32| {
+                let Point { x, y } = sheet.point((block.x + dx * block.width / 2) as f32, (block.y + dy * block.height / 2) as f32);
+                (x.0, y.0)
+            };
+            let (left, bottom) = corner(call, &sheets[page], -1, 1);
+            let (right, top) = corner(call, &sheets[page], 1, -1);
+            Some(PageLink {
+                page,
+                rect: [left, bottom, right, top],
+                target,
+                destination: corner(start, &sheets[target], -1, -1),
+            })
+        })
+        .collect()
+}
+
+/// Adds the links as annotations with a go-to action; printpdf itself can
+/// only link to URIs.
+fn add_links(pdf: Vec<u8>, links: &[PageLink]) -> Result<Vec<u8>> {
+    if links.is_empty() {
+        return Ok(pdf);
+    }
+    let mut document = lopdf::Document::load_mem(&pdf).context("Failed to reopen PDF")?;
+    let pages: Vec<lopdf::ObjectId> = document.get_pages().into_values().collect();
+    for link in links {
+        let (Some(&page), Some(&target)) = (pages.get(link.page), pages.get(link.target)) else {
+            continue;
+        };
+        let (x, y) = link.destination;
+        let annotation = document.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Link",
+            "Rect" => link.rect.iter().map(|&edge| Object::Real(edge)).collect::<Vec<_>>(),
+            "Border" => vec![0.into(), 0.into(), 0.into()],
+            "A" => dictionary! {
+                "S" => "GoTo",
+                "D" => vec![target.into(), "XYZ".into(), Object::Real(x), Object::Real(y), Object::Null],
+            },
+        });
+        let page = document.get_object_mut(page)?.as_dict_mut()?;
+        match page.get_mut(b"Annots") {
+            Ok(Object::Array(annotations)) => annotations.push(annotation.into()),
+            _ => page.set("Annots", vec![annotation.into()]),
+        }
+    }
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes).context("Failed to write PDF")?;
+    Ok(bytes)
+}
+
+/// Pages laid out by `paginate`, left to right with `PAGE_GAP` between.
+fn page_sheets(diagram: &FullJson, page: PageSize) -> Vec<Sheet> {
+    let (width, height) = page.dimensions();
+    let page_of = |x: i32| x.div_euclid(width + PAGE_GAP).max(0) as usize;
+    let count = diagram.blocks.iter().map(|b| page_of(b.x) + 1).max().unwrap_or(1);
+    (0..count)
+        .map(|index| Sheet {
+            left: index as i32 * (width + PAGE_GAP),
+            top: 0,
+            width,
+            height,
+            blocks: diagram.blocks.iter().map(|b| page_of(b.x) == index).collect(),
+        })
+        .collect()
+}
+
+/// One page per function: functions are drawn one below another, each
+/// starting at a terminator no arrow leads to.
+fn function_sheets(diagram: &FullJson, routes: &[Vec<Node>], margin: i32) -> Vec<Sheet> {
+    let entered: HashSet<usize> = diagram.arrows.iter()
+        .filter(|a| !a.dashed)
+        .map(|a| a.end_index)
+        .collect();
+    let mut starts: Vec<i32> = diagram.blocks.iter()
+        .enumerate()
+        .filter(|(index, b)| b.r#type == "Начало / конец" && !entered.contains(index))
+        .map(|(_, b)| b.y - b.height / 2)
+        .collect();
+    starts.sort_unstable();
+    starts.dedup();
+    match starts.first_mut() {
+        Some(first) => *first = i32::MIN,
+        None => starts.push(i32::MIN),
+    }
+
+    let mut sheets = Vec::with_capacity(starts.len());
+    for (index, &from) in starts.iter().enumerate() {
+        let to = starts.get(index + 1).copied().unwrap_or(i32::MAX);
+        let blocks: Vec<bool> = diagram.blocks.iter().map(|b| b.y >= from && b.y < to).collect();
+        let members = diagram.blocks.iter().zip(&blocks).filter(|(_, &on)| on).map(|(b, _)| b);
+        let arrows = diagram.arrows.iter()
+            .zip(routes)
+            .filter(|(a, _)| blocks.get(a.start_index).copied().unwrap_or(false))
+            .map(|(_, route)| route);
+        let (left, top, right, bottom) = bounds(members, arrows);
+        sheets.push(Sheet {
+            left: left - margin,
+            top: top - margin,
+            width: right - left + 2 * margin,
+            height: bottom - top + 2 * margin,
+            blocks,
+        });
+    }
+    sheets
+}
+
+fn draw_sheet(
+    layer: &PdfLayerReference,
+    diagram: &FullJson,
+    routes: &[Vec<Node>],
+    sheet: &Sheet,
+    font: &IndirectFontRef,
+) {
+    let black = Color::Greyscale(Greyscale::new(0.0, None));
+    let white = Color::Greyscale(Greyscale::new(1.0, None));
+    layer.set_outline_color(black.clone());
+    layer.set_outline_thickness(LINE_WIDTH * PT_PER_PX);
+
+    layer.set_fill_color(white);
+    for (block, _) in diagram.blocks.iter().zip(&sheet.blocks).filter(|(_, &on)| on) {
+        for figure in outline(block) {
+            let points = figure.points.iter().map(|&(x, y)| (sheet.point(x, y), false)).collect();
+            if figure.closed {
+                layer.add_polygon(Polygon {
+                    rings: vec![points],
+                    mode: PaintMode::FillStroke,
+                    winding_order: WindingOrder::NonZero,
+                });
+            } else {
+                layer.add_line(Line { points, is_closed: false });
+            }
+        }
+    }
+
+    layer.set_fill_color(black);
+    let arrows = diagram.arrows.iter()
+        .zip(routes)
+        .filter(|(a, _)| sheet.blocks.get(a.start_index).copied().unwrap_or(false));
+    for (arrow, points) in arrows.clone() {
+        if arrow.dashed {
+            layer.set_line_dash_pattern(LineDashPattern {
+                dash_1: Some(5),
+                gap_1: Some(3),
+                ..LineDashPattern::default()
+            });
+        }
+        let line = points.iter().map(|p| (sheet.point(p.x as f32, p.y as f32), false)).collect();
+        layer.add_line(Line { points: line, is_closed: false });
+        if arrow.dashed {
+            layer.set_line_dash_pattern(LineDashPattern::default());
+        } else if let Some(head) = arrowhead(points) {
+            layer.add_polygon(Polygon {
+                rings: vec![head.iter().map(|&(x, y)| (sheet.point(x, y), false)).collect()],
+                mode: PaintMode::Fill,
+                winding_order: WindingOrder::NonZero,
+            });
+        }
+    }
+
+    for (block, _) in diagram.blocks.iter().zip(&sheet.blocks).filter(|(_, &on)| on) {
+        for line in caption_lines(block) {
+            draw_text(layer, sheet, &line, font);
+        }
+    }
+    for (arrow, points) in arrows {
+        if let Some(line) = label_line(arrow, points) {
+            draw_text(layer, sheet, &line, font);
+        }
+    }
+}
+
+fn draw_text(layer: &PdfLayerReference, sheet: &Sheet, line: &TextLine, font: &IndirectFontRef) {
+    let width = text_width(line.text);
+    let x = match line.anchor {
+        Anchor::Start => line.x,
+        Anchor::Middle => line.x - width / 2.0,
+        Anchor::End => line.x - width,
+    };
+    let Point { x, y } = sheet.point(x, line.y);
+    layer.use_text(line.text, FONT_SIZE as f32 * PT_PER_PX, x.into(), y.into(), font);
+}
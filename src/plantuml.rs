@@ -0,0 +1,224 @@
+use crate::config::{BranchLabels, Config};
+use crate::lang_vec_stuf::{loop_label, BlockType, CodeBlock};
+use crate::structure::{functions, header_text, is_return, Statement};
+
+/// Writes the analysed blocks as a PlantUML activity diagram. It follows
+/// the nesting of the blocks instead of their coordinates, so every `if`,
+/// `match` and loop of the source becomes the matching PlantUML construct
+/// and PlantUML does the layout.
+pub fn render_plantuml(blocks: &[CodeBlock], config: &Config) -> String {
+    let mut writer = Writer {
+        depth: 0,
+        labels: &config.branch_labels,
+        out: String::from("@startuml\n"),
+    };
+    for function in functions(blocks) {
+        let Some(start) = function.start else {
+            writer.sequence(&function.body);
+            continue;
+        };
+        let name = start.link.as_deref().unwrap_or(&start.text);
+        writer.line(&format!("partition \"{}\" {{", name.replace('"', "'")));
+        writer.depth += 1;
+        writer.line("start");
+        writer.note(start);
+        writer.sequence(&function.body);
+        if !function.body.last().is_some_and(Statement::stops) {
+            writer.line("stop");
+        }
+        writer.depth -= 1;
+        writer.line("}");
+    }
+    writer.out.push_str("@enduml\n");
+    writer.out
+}
+
+struct Writer<'a> {
+    depth: usize,
+    labels: &'a BranchLabels,
+    out: String,
+}
+
+impl Writer<'_> {
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&"    ".repeat(self.depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn sequence(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn nested(&mut self, statements: &[Statement]) {
+        self.indented(|writer| writer.sequence(statements));
+    }
+
+    fn indented(&mut self, write: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        write(self);
+        self.depth -= 1;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Simple(block) => self.simple(block),
+            Statement::If { block, then, otherwise } => {
+                self.floating_note(block);
+                let yes = label(&self.labels.yes);
+                self.line(&format!("if ({}) then{yes}", escape(header_text(block))));
+                self.nested(then);
+                if let Some(otherwise) = otherwise {
+                    let no = label(&self.labels.no);
+                    self.line(&format!("else{no}"));
+                    self.nested(otherwise);
+                }
+                self.line("endif");
+            }
+            Statement::Match { block, arms } => {
+                self.floating_note(block);
+                self.line(&format!("switch ({})", escape(header_text(block))));
+                for arm in arms {
+                    self.line(&format!("case ({})", escape(arm.label)));
+                    self.nested(&arm.body);
+                }
+                self.line("endswitch");
+            }
+            Statement::Parallel { block, arms } => {
+                self.action(block, ';');
+                for (index, arm) in arms.iter().enumerate() {
+                    self.line(if index == 0 { "fork" } else { "fork again" });
+                    self.nested(arm);
+                }
+                if !arms.is_empty() {
+                    self.line("end fork");
+                }
+            }
+            Statement::Loop { block, header: None, body, .. } => {
+                self.floating_note(block);
+                self.line("repeat");
+                self.nested(body);
+                self.line("repeat while (true)");
+            }
+            Statement::Loop { block, header: Some(header), step, body } => {
+                self.floating_note(block);
+                let yes = &self.labels.yes;
+                let condition = escape(&loop_condition(header));
+                let line = if yes.is_empty() {
+                    format!("while ({condition})")
+                } else {
+                    format!("while ({condition}) is ({})", escape(yes))
+                };
+                self.line(&line);
+                self.nested(body);
+                if let Some(step) = step {
+                    self.indented(|writer| writer.line(&format!(":{};", escape(step))));
+                }
+                let no = label(&self.labels.no);
+                self.line(&format!("endwhile{no}"));
+            }
+        }
+    }
+
+    fn simple(&mut self, block: &CodeBlock) {
+        match block.r#type {
+            BlockType::Print => self.action(block, '>'),
+            BlockType::Input => self.action(block, '<'),
+            BlockType::Subroutine => self.action(block, '|'),
+            BlockType::Terminate => {
+                self.action(block, ';');
+                self.line("detach");
+            }
+            _ if is_return(block) => {
+                self.action(block, ';');
+                self.line("stop");
+            }
+            _ => self.action(block, ';'),
+        }
+    }
+
+    fn action(&mut self, block: &CodeBlock, shape: char) {
+        self.line(&format!(":{}{shape}", escape(block.full_caption())));
+        self.note(block);
+    }
+
+    fn note(&mut self, block: &CodeBlock) {
+        if let Some(comment) = &block.comment {
+            self.line("note right");
+            for line in comment.lines() {
+                self.line(&format!("    {line}"));
+            }
+            self.line("end note");
+        }
+    }
+
+    /// Comment of a compound statement, which has no single shape for a
+    /// note to stick to.
+    fn floating_note(&mut self, block: &CodeBlock) {
+        if let Some(comment) = &block.comment {
+            self.line(&format!("floating note right: {}", escape(comment)));
+        }
+    }
+}
+
+/// Loop header as the condition a `while` tests: `for x in xs` asks
+/// whether `xs` has one more element. A loop label stays in front.
+fn loop_condition(header: &str) -> String {
+    let (label, header) = match loop_label(header) {
+        Some(label) => (format!("{label}: "), header[label.len() + 1..].trim_start()),
+        None => (String::new(), header),
+    };
+    let header = header.strip_prefix("while ").unwrap_or(header);
+    match header.strip_prefix("for ").and_then(|rest| rest.split_once(" in ")) {
+        Some((pattern, items)) => format!("{label}есть очередной {pattern} из {items}"),
+        None => format!("{label}{header}"),
+    }
+}
+
+/// Branch label in parentheses, or nothing when labels are turned off.
+fn label(text: &str) -> String {
+    if text.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", escape(text))
+    }
+}
+
+/// One-line text: source line breaks become PlantUML `\n`, so a line of
+/// the source ending in `;` does not end the action early.
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CountedLoops;
+    use crate::lang_vec_stuf::{Language, Rust};
+
+    fn plantuml(body: &str, config: &Config) -> String {
+        let source = format!("fn f() {{\n{body}\n}}\n");
+        render_plantuml(&Rust.analyze_to_vec(source, config), config)
+    }
+
+    #[test]
+    fn for_headers_become_conditions() {
+        let uml = plantuml("for x in xs {\n    go(x);\n}", &Config::default());
+        assert!(uml.contains("while (есть очередной x из xs) is (да)"), "{uml}");
+        let uml = plantuml("'outer: for i in 0..n {\n    go(i);\n}", &Config::default());
+        assert!(uml.contains("while ('outer: есть очередной i из 0..n)"), "{uml}");
+    }
+
+    #[test]
+    fn counted_step_ends_the_body() {
+        let config = Config { counted_loops: CountedLoops::Decomposed, ..Config::default() };
+        let uml = plantuml("for i in 0..n {\n    go(i);\n}", &config);
+        assert!(uml.contains("while (i < n) is (да)\n        :go(i);\n        :i = i + 1;\n    endwhile"), "{uml}");
+    }
+}
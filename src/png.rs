@@ -0,0 +1,27 @@
+use anyhow::{Context, Result};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+
+use crate::config::Config;
+use crate::mk_json_blocks::FullJson;
+use crate::sizing::FONT;
+use crate::svg::render_svg;
+
+/// Rasterises the SVG drawing of a diagram on the CPU with anti-aliasing,
+/// using the bundled font so the result does not depend on the machine.
+pub fn render_png(diagram: &FullJson, config: &Config) -> Result<Vec<u8>> {
+    let svg = render_svg(diagram, config.background);
+
+    let mut options = Options {
+        font_family: String::from("DejaVu Sans"),
+        ..Options::default()
+    };
+    options.fontdb_mut().load_font_data(FONT.to_vec());
+    let tree = Tree::from_str(&svg, &options).context("Failed to parse the drawing")?;
+
+    let scale = config.dpi as f32 / 96.0;
+    let size = tree.size().to_int_size().scale_by(scale).context("Image is too large")?;
+    let mut pixmap = Pixmap::new(size.width(), size.height()).context("Image is too large")?;
+    resvg::render(&tree, Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+    pixmap.encode_png().context("Failed to encode PNG")
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use ttf_parser::{Face, GlyphId};
+
+use crate::lang_vec_stuf::{BlockType, CodeBlock};
+use crate::wrap::is_overlong;
+
+/// DejaVu Sans Condensed: covers Cyrillic and is bundled, so text is
+/// measured and drawn with the same font on any machine.
+pub const FONT: &[u8] = include_bytes!("../assets/DejaVuSansCondensed.ttf");
+static FACE: LazyLock<Face<'static>> = LazyLock::new(|| Face::parse(FONT, 0).expect("Bundled font is broken"));
+pub const FONT_SIZE: i32 = 14;
+pub const LINE_HEIGHT: f32 = FONT_SIZE as f32 * 1.2;
+/// Free space between the text and the outline of a block.
+pub const PADDING: f32 = 8.0;
+/// GOST 19.701 sizes: the height `a` grows in steps of 20 (about 5 mm) and
+/// the width is always `b = 1.5a`.
+const A_MIN: i32 = 40;
+const A_MAX: i32 = 160;
+const A_STEP: i32 = 20;
+/// Smallest free space left between neighbouring blocks.
+pub const GAP: i32 = 40;
+
+pub const CONNECTOR_SIZE: (i32, i32) = (40, 40);
+pub const OFF_PAGE_CONNECTOR_SIZE: (i32, i32) = (60, 50);
+
+/// Width of one line of text at `FONT_SIZE`, measured with the advances
+/// of the bundled font that PNG and PDF draw it with. Characters the font
+/// lacks count as its replacement box.
+pub fn text_width(line: &str) -> f32 {
+    let units: u32 = line.chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .map(|c| FACE.glyph_index(c).unwrap_or(GlyphId(0)))
+        .filter_map(|glyph| FACE.glyph_hor_advance(glyph))
+        .map(u32::from)
+        .sum();
+    units as f32 * FONT_SIZE as f32 / f32::from(FACE.units_per_em())
+}
+
+/// Width and height of a block of text, one line per `\n`.
+pub fn text_size(text: &str) -> (f32, f32) {
+    let width = text.lines().map(text_width).fold(0.0, f32::max);
+    let lines = text.lines().count().max(1);
+    (width, lines as f32 * LINE_HEIGHT)
+}
+
+/// Whether text of the given size fits into a `b` x `a` block of this type.
+fn fits(block_type: BlockType, (width, height): (f32, f32), b: i32, a: i32) -> bool {
+    let (width, height) = (width + 2.0 * PADDING, height + 2.0 * PADDING);
+    let (b, a) = (b as f32, a as f32);
+    match block_type {
+        // ромб: текст должен поместиться во вписанный прямоугольник
+        BlockType::Condition | BlockType::Switch | BlockType::LoopTest => width / b + height / a <= 1.0,
+        // скошенные или скруглённые края съедают по a/8 с каждой стороны
+        BlockType::Print
+        | BlockType::Input
+        | BlockType::Start
+        | BlockType::End
+        | BlockType::Terminate
+        | BlockType::Cycle
+        | BlockType::Preparation
+        | BlockType::LoopStart
+        | BlockType::LoopEnd => width <= b - a / 4.0 && height <= a,
+        // боковые линии предопределённого процесса
+        BlockType::Subroutine => width <= b * 0.75 && height <= a,
+        _ => width <= b && height <= a,
+    }
+}
+
+/// Smallest GOST-proportioned size of `block` that fits its caption
+/// wrapped to `wrap_width` characters.
+pub fn block_size(block: &CodeBlock, wrap_width: usize) -> (i32, i32) {
+    match block.r#type {
+        BlockType::Connector => return CONNECTOR_SIZE,
+        BlockType::OffPageConnector => return OFF_PAGE_CONNECTOR_SIZE,
+        _ if block.is_marker() => return (0, 0),
+        _ => {}
+    }
+    let text = text_size(&block.caption(wrap_width));
+    let a = (A_MIN..=A_MAX)
+        .step_by(A_STEP as usize)
+        .find(|&a| fits(block.r#type, text, a * 3 / 2, a))
+        .unwrap_or(A_MAX);
+    (a * 3 / 2, a)
+}
+
+/// Size of a comment annotation, which is not bound to GOST proportions.
+pub fn comment_size(text: &str) -> (i32, i32) {
+    let (width, height) = text_size(text);
+    ((width + 2.0 * PADDING).ceil() as i32, (height + 2.0 * PADDING).ceil() as i32)
+}
+
+/// Measures every block and spreads rows and columns apart so that
+/// neighbouring blocks keep at least `GAP` between them: the analyser lays
+/// blocks out on a fixed grid of 100 units. Statements too long to be
+/// written in a block are also written out in full in its comment.
+pub fn fit_blocks(blocks: &mut [CodeBlock], wrap_width: usize) {
+    for block in blocks.iter_mut() {
+        if !block.is_marker() && is_overlong(block.full_caption(), wrap_width) {
+            let full = block.full_caption().to_string();
+            block.comment = Some(match block.comment.take() {
+                Some(comment) => format!("{full}\n{comment}"),
+                None => full,
+            });
+        }
+        block.size = block_size(block, wrap_width);
+    }
+
+    let rows = spread(blocks.iter().map(|b| (b.y, b.size.1)));
+    let columns = spread(blocks.iter().map(|b| (b.x, b.size.0)));
+    for block in blocks.iter_mut() {
+        if let Some((x, y)) = block.loop_origin() {
+            let step = block.loop_step().map(|step| format!(":{step}")).unwrap_or_default();
+            block.text = format!("{}:{}{step}", columns[&x], rows[&y]);
+        }
+        block.x = columns[&block.x];
+        block.y = rows[&block.y];
+    }
+}
+
+/// New coordinates along one axis for `(position, extent)` pairs: every
+/// distinct position keeps its distance to the previous one unless the
+/// largest blocks at both need more. Position 0 stays in place.
+fn spread(items: impl Iterator<Item = (i32, i32)>) -> BTreeMap<i32, i32> {
+    let mut extents = BTreeMap::new();
+    for (position, extent) in items {
+        let largest = extents.entry(position).or_insert(0);
+        *largest = extent.max(*largest);
+    }
+
+    let mut positions = BTreeMap::new();
+    let mut previous: Option<(i32, i32, i32)> = None;
+    for (&position, &extent) in &extents {
+        let moved = match previous {
+            Some((old, new, previous_extent)) => {
+                new + (position - old).max((previous_extent + extent) / 2 + GAP)
+            }
+            None => position,
+        };
+        positions.insert(position, moved);
+        previous = Some((position, moved, extent));
+    }
+
+    if let Some(&zero) = positions.get(&0) {
+        for position in positions.values_mut() {
+            *position -= zero;
+        }
+    }
+    positions
+}
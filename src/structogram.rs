@@ -0,0 +1,405 @@
+use crate::config::{Background, BranchLabels, Config};
+use crate::lang_vec_stuf::{BlockType, CodeBlock};
+use crate::sizing::{text_size, FONT_SIZE, LINE_HEIGHT, PADDING};
+use crate::structure::{functions, header_text, is_return, Arm, Function, Statement};
+use crate::svg::{escape, FONT_FAMILY};
+use crate::wrap::caption_text;
+
+/// Free space around the drawing and between functions.
+const MARGIN: f32 = 20.0;
+/// Width of the bar on the left of a loop body.
+const INDENT: f32 = 24.0;
+/// Height of the bar closing an endless loop or a parallel block.
+const BAR: f32 = PADDING;
+const MIN_WIDTH: f32 = 60.0;
+
+/// Draws the functions as Nassi–Shneiderman diagrams (DIN 66261), one
+/// below another: statements are stacked rectangles, `if` and `match`
+/// split into columns under a triangle and loops wrap their body in an
+/// L-shaped bar. Sizes come from the nesting alone, not from the layout
+/// of the flowchart.
+pub fn render_structogram(blocks: &[CodeBlock], config: &Config) -> String {
+    let layout = Layout {
+        wrap_width: config.wrap_width,
+        labels: &config.branch_labels,
+    };
+    let functions = functions(blocks);
+
+    let mut body = String::new();
+    let mut y = MARGIN;
+    let mut right = 0.0_f32;
+    for function in &functions {
+        let width = layout.function_width(function);
+        y += layout.draw_function(function, MARGIN, y, width, &mut body) + MARGIN;
+        right = right.max(MARGIN + width);
+    }
+    let (width, height) = ((right + MARGIN).ceil(), y.ceil());
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" font-family=\"{FONT_FAMILY}\" font-size=\"{FONT_SIZE}\">\n"
+    );
+    if config.background == Background::White {
+        svg.push_str(&format!("<rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>\n"));
+    }
+    svg.push_str("<g fill=\"none\" stroke=\"black\" stroke-width=\"1.5\">\n");
+    svg.push_str(&body);
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+struct Layout<'a> {
+    wrap_width: usize,
+    labels: &'a BranchLabels,
+}
+
+/// Wrapped text with the size of the box it needs.
+struct Text {
+    lines: String,
+    width: f32,
+    height: f32,
+}
+
+impl Layout<'_> {
+    fn text(&self, text: &str) -> Text {
+        let lines = caption_text(text, self.wrap_width);
+        let (width, height) = text_size(&lines);
+        Text {
+            lines,
+            width: width + 2.0 * PADDING,
+            height: height + 2.0 * PADDING,
+        }
+    }
+
+    fn title(&self, function: &Function) -> Option<Text> {
+        let start = function.start?;
+        Some(self.text(start.link.as_deref().unwrap_or(&start.text)))
+    }
+
+    fn function_width(&self, function: &Function) -> f32 {
+        let title = self.title(function).map_or(0.0, |t| t.width);
+        title.max(self.sequence_width(&function.body))
+    }
+
+    /// Draws a function with its name in a band on top; returns the height.
+    fn draw_function(&self, function: &Function, x: f32, y: f32, width: f32, svg: &mut String) -> f32 {
+        let top = match self.title(function) {
+            Some(title) => {
+                rect(svg, x, y, width, title.height);
+                text(svg, &title.lines, x + PADDING, y + title.height / 2.0, "start");
+                title.height
+            }
+            None => 0.0,
+        };
+        let height = self.sequence_height(&function.body, width);
+        self.draw_sequence(&function.body, x, y + top, width, height, svg);
+        top + height
+    }
+
+    fn sequence_width(&self, statements: &[Statement]) -> f32 {
+        statements.iter().map(|s| self.width(s)).fold(MIN_WIDTH, f32::max)
+    }
+
+    fn sequence_height(&self, statements: &[Statement], width: f32) -> f32 {
+        if statements.is_empty() {
+            return LINE_HEIGHT + 2.0 * PADDING;
+        }
+        statements.iter().map(|s| self.height(s, width)).sum()
+    }
+
+    /// Draws statements one below another; the last one is stretched to
+    /// fill `height`, so neighbouring columns end on the same line.
+    fn draw_sequence(&self, statements: &[Statement], x: f32, y: f32, width: f32, height: f32, svg: &mut String) {
+        if statements.is_empty() {
+            rect(svg, x, y, width, height);
+            return;
+        }
+        let mut top = y;
+        for (index, statement) in statements.iter().enumerate() {
+            let own = if index + 1 == statements.len() {
+                y + height - top
+            } else {
+                self.height(statement, width)
+            };
+            self.draw(statement, x, top, width, own, svg);
+            top += own;
+        }
+    }
+
+    /// Smallest width the statement can be drawn in.
+    fn width(&self, statement: &Statement) -> f32 {
+        match statement {
+            Statement::Simple(block) => {
+                let marks = if exits(block) || block.r#type == BlockType::Subroutine { 2.0 * INDENT } else { 0.0 };
+                self.text(block.full_caption()).width + marks
+            }
+            Statement::If { block, then, otherwise } => {
+                let branches = self.sequence_width(then) + otherwise.as_deref().map_or(MIN_WIDTH, |s| self.sequence_width(s));
+                let labels = self.text(&self.labels.yes).width + self.text(&self.labels.no).width;
+                branches.max(self.text(header_text(block)).width * 2.0).max(labels)
+            }
+            Statement::Match { block, arms } => {
+                let columns: f32 = arms.iter()
+                    .map(|arm| self.arm_width(arm))
+                    .sum();
+                columns.max(self.text(header_text(block)).width * 2.0)
+            }
+            Statement::Parallel { block, arms } => {
+                let columns: f32 = arms.iter().map(|arm| self.sequence_width(arm)).sum();
+                columns.max(self.text(block.full_caption()).width)
+            }
+            Statement::Loop { block, header, step, body } => {
+                let step = step.map_or(0.0, |step| self.text(step).width);
+                let body = INDENT + self.sequence_width(body).max(step);
+                body.max(self.text(header.unwrap_or(block.full_caption())).width)
+            }
+        }
+        .max(MIN_WIDTH)
+    }
+
+    /// Height the statement needs when drawn `width` wide.
+    fn height(&self, statement: &Statement, width: f32) -> f32 {
+        match statement {
+            Statement::Simple(block) => self.text(block.full_caption()).height,
+            Statement::If { block, then, otherwise } => {
+                let [left, right] = self.if_columns(then, otherwise.as_deref(), width);
+                let otherwise = otherwise.as_deref().unwrap_or_default();
+                self.if_header(block, width)
+                    + self.sequence_height(then, left).max(self.sequence_height(otherwise, right))
+            }
+            Statement::Match { block, arms } => {
+                let widths = self.match_columns(arms, width);
+                let body = arms.iter()
+                    .zip(&widths)
+                    .map(|(arm, &w)| self.sequence_height(&arm.body, w))
+                    .fold(0.0, f32::max);
+                self.match_header(block, arms, &widths) + body
+            }
+            Statement::Parallel { block, arms } => {
+                let widths = split(width, &arms.iter().map(|arm| self.sequence_width(arm)).collect::<Vec<_>>());
+                let body = arms.iter()
+                    .zip(&widths)
+                    .map(|(arm, &w)| self.sequence_height(arm, w))
+                    .fold(LINE_HEIGHT + 2.0 * PADDING, f32::max);
+                self.text(block.full_caption()).height + body + BAR
+            }
+            Statement::Loop { block, header, step, body } => {
+                let inner = width - INDENT;
+                let step = step.map_or(0.0, |step| self.text(step).height);
+                let bar = if header.is_none() { BAR } else { 0.0 };
+                self.text(header.unwrap_or(block.full_caption())).height
+                    + self.sequence_height(body, inner)
+                    + step
+                    + bar
+            }
+        }
+    }
+
+    fn draw(&self, statement: &Statement, x: f32, y: f32, width: f32, height: f32, svg: &mut String) {
+        match statement {
+            Statement::Simple(block) => {
+                rect(svg, x, y, width, height);
+                let caption = self.text(block.full_caption());
+                if exits(block) {
+                    // выход: стрелка влево у левого края
+                    line(svg, &[(x + INDENT, y), (x + INDENT / 2.0, y + height / 2.0), (x + INDENT, y + height)]);
+                } else if block.r#type == BlockType::Subroutine {
+                    line(svg, &[(x + INDENT / 2.0, y), (x + INDENT / 2.0, y + height)]);
+                    line(svg, &[(x + width - INDENT / 2.0, y), (x + width - INDENT / 2.0, y + height)]);
+                }
+                text(svg, &caption.lines, x + width / 2.0, y + height / 2.0, "middle");
+            }
+            Statement::If { block, then, otherwise } => {
+                let [left, right] = self.if_columns(then, otherwise.as_deref(), width);
+                let header = self.if_header(block, width);
+                let apex = (x + left, y + header);
+                rect(svg, x, y, width, header);
+                line(svg, &[(x, y), apex, (x + width, y)]);
+
+                let caption = self.text(header_text(block));
+                let center = gap_center(x, left, width, caption.height / header);
+                text(svg, &caption.lines, center, y + caption.height / 2.0, "middle");
+                let labels = y + header - PADDING / 2.0 - LINE_HEIGHT / 2.0;
+                text(svg, &self.labels.yes, x + PADDING, labels, "start");
+                text(svg, &self.labels.no, x + width - PADDING, labels, "end");
+
+                let rest = height - header;
+                self.draw_sequence(then, x, y + header, left, rest, svg);
+                self.draw_sequence(otherwise.as_deref().unwrap_or_default(), x + left, y + header, right, rest, svg);
+            }
+            Statement::Match { block, arms } => {
+                let widths = self.match_columns(arms, width);
+                let header = self.match_header(block, arms, &widths);
+                let last = x + width - widths.last().copied().unwrap_or(width);
+                rect(svg, x, y, width, header);
+                line(svg, &[(x, y), (last, y + header), (x + width, y)]);
+
+                let caption = self.text(header_text(block));
+                let center = gap_center(x, last - x, width, caption.height / header);
+                text(svg, &caption.lines, center, y + caption.height / 2.0, "middle");
+
+                let mut left = x;
+                for (arm, &w) in arms.iter().zip(&widths) {
+                    if left > x {
+                        // граница столбцов идёт от диагонали до низа шапки
+                        let top = y + header * (left - x) / (last - x);
+                        line(svg, &[(left, top), (left, y + header)]);
+                    }
+                    // подписи прижаты к углам, подальше от вершины треугольника
+                    let label = self.text(arm.label);
+                    let (at, anchor) = if left + w < x + width {
+                        (left + PADDING, "start")
+                    } else {
+                        (left + w - PADDING, "end")
+                    };
+                    text(svg, &label.lines, at, y + header - label.height / 2.0, anchor);
+                    self.draw_sequence(&arm.body, left, y + header, w, height - header, svg);
+                    left += w;
+                }
+            }
+            Statement::Parallel { block, arms } => {
+                let caption = self.text(block.full_caption());
+                let widths = split(width, &arms.iter().map(|arm| self.sequence_width(arm)).collect::<Vec<_>>());
+                rect(svg, x, y, width, height);
+                line(svg, &[(x, y + caption.height), (x + width, y + caption.height)]);
+                line(svg, &[(x, y + height - BAR), (x + width, y + height - BAR)]);
+                text(svg, &caption.lines, x + width / 2.0, y + caption.height / 2.0, "middle");
+
+                let mut left = x;
+                for (arm, &w) in arms.iter().zip(&widths) {
+                    self.draw_sequence(arm, left, y + caption.height, w, height - caption.height - BAR, svg);
+                    left += w;
+                }
+            }
+            Statement::Loop { block, header, step, body } => {
+                let caption = self.text(header.unwrap_or(block.full_caption()));
+                let bar = if header.is_none() { BAR } else { 0.0 };
+                let step = step.map(|step| self.text(step));
+                let step_height = step.as_ref().map_or(0.0, |s| s.height);
+                rect(svg, x, y, width, height);
+                text(svg, &caption.lines, x + PADDING, y + caption.height / 2.0, "start");
+
+                let inner = (x + INDENT, y + caption.height, width - INDENT);
+                let body_height = height - caption.height - step_height - bar;
+                self.draw_sequence(body, inner.0, inner.1, inner.2, body_height, svg);
+                if let Some(step) = step {
+                    let top = inner.1 + body_height;
+                    rect(svg, inner.0, top, inner.2, step.height);
+                    text(svg, &step.lines, inner.0 + inner.2 / 2.0, top + step.height / 2.0, "middle");
+                }
+            }
+        }
+    }
+
+    fn if_header(&self, block: &CodeBlock, width: f32) -> f32 {
+        let caption = self.text(header_text(block));
+        (caption.height + LINE_HEIGHT).max(under_diagonals(&caption, width))
+    }
+
+    fn if_columns(&self, then: &[Statement], otherwise: Option<&[Statement]>, width: f32) -> [f32; 2] {
+        let otherwise = otherwise.map_or(MIN_WIDTH, |s| self.sequence_width(s));
+        let widths = split(width, &[self.sequence_width(then), otherwise]);
+        [widths[0], widths[1]]
+    }
+
+    /// Height of the triangle over the arms: tall enough for the arm labels
+    /// to stay under the diagonal, within three label rows.
+    fn match_header(&self, block: &CodeBlock, arms: &[Arm], widths: &[f32]) -> f32 {
+        let total: f32 = widths.iter().sum();
+        let labels = arms.iter().map(|arm| self.text(arm.label).height).fold(0.0, f32::max);
+        let caption = self.text(header_text(block));
+        let base = (caption.height + labels).max(under_diagonals(&caption, total));
+        let last = total - widths.last().copied().unwrap_or(total);
+        if last <= 0.0 {
+            return base;
+        }
+        let mut header = base;
+        let mut left = 0.0;
+        for (index, (arm, &w)) in arms.iter().zip(widths).enumerate() {
+            let label = self.text(arm.label).width;
+            // доля высоты шапки под диагональю у дальнего от угла края подписи
+            let free = if index + 1 < arms.len() {
+                (last - left - label) / last
+            } else {
+                (left + w - label - last) / (total - last)
+            };
+            if free > 0.0 {
+                header = header.max(labels / free);
+            }
+            left += w;
+        }
+        header.min(base + 2.0 * labels)
+    }
+
+    /// Arm labels get half their width again, so the diagonal over the
+    /// column clears them.
+    fn arm_width(&self, arm: &Arm) -> f32 {
+        self.sequence_width(&arm.body).max(self.text(arm.label).width * 1.5)
+    }
+
+    fn match_columns(&self, arms: &[Arm], width: f32) -> Vec<f32> {
+        let widths: Vec<f32> = arms.iter()
+            .map(|arm| self.arm_width(arm))
+            .collect();
+        split(width, &widths)
+    }
+}
+
+/// Height of a triangle `width` wide that fits `caption` between its
+/// diagonals at the top: at depth `h` they leave `width * (1 - h / header)`.
+fn under_diagonals(caption: &Text, width: f32) -> f32 {
+    if width > caption.width {
+        caption.height * width / (width - caption.width)
+    } else {
+        caption.height
+    }
+}
+
+/// Middle of the space between the diagonals of a triangle with its apex
+/// `apex` to the right of `x`, at `depth` of its height.
+fn gap_center(x: f32, apex: f32, width: f32, depth: f32) -> f32 {
+    x + apex * depth + width * (1.0 - depth) / 2.0
+}
+
+/// Shares `width` between columns in proportion to their smallest widths.
+fn split(width: f32, minimums: &[f32]) -> Vec<f32> {
+    let total: f32 = minimums.iter().sum();
+    if total <= 0.0 {
+        return vec![width / minimums.len().max(1) as f32; minimums.len()];
+    }
+    minimums.iter().map(|m| m * width / total).collect()
+}
+
+/// Statements that leave the function or the loop: drawn with the exit
+/// mark of DIN 66261.
+fn exits(block: &CodeBlock) -> bool {
+    matches!(block.r#type, BlockType::Terminate)
+        || is_return(block)
+        || block.text == "break"
+        || block.text.starts_with("break ")
+}
+
+fn rect(svg: &mut String, x: f32, y: f32, width: f32, height: f32) {
+    svg.push_str(&format!("<rect x=\"{x}\" y=\"{y}\" width=\"{width}\" height=\"{height}\"/>\n"));
+}
+
+fn line(svg: &mut String, points: &[(f32, f32)]) {
+    let points = points.iter()
+        .map(|(x, y)| format!("{x},{y}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    svg.push_str(&format!("<polyline points=\"{points}\"/>\n"));
+}
+
+/// Lines of text centred vertically on `y`.
+fn text(svg: &mut String, lines: &str, x: f32, y: f32, anchor: &str) {
+    let count = lines.lines().count();
+    let first = y - (count.max(1) - 1) as f32 * LINE_HEIGHT / 2.0 + 0.35 * FONT_SIZE as f32;
+    for (index, line) in lines.lines().enumerate() {
+        svg.push_str(&format!(
+            "<text x=\"{x}\" y=\"{}\" text-anchor=\"{anchor}\" fill=\"black\" stroke=\"none\">{}</text>\n",
+            first + index as f32 * LINE_HEIGHT,
+            escape(line)
+        ));
+    }
+}
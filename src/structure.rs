@@ -0,0 +1,277 @@
+use crate::lang_vec_stuf::{BlockType, CodeBlock};
+
+/// Function body rebuilt from the flat block list, for the formats drawn
+/// from the nesting of statements rather than from block coordinates.
+pub struct Function<'a> {
+    /// `None` for statements that stand before the first function.
+    pub start: Option<&'a CodeBlock>,
+    pub body: Vec<Statement<'a>>,
+}
+
+pub enum Statement<'a> {
+    /// Action, input/output, call, `return` or `panic!`.
+    Simple(&'a CodeBlock),
+    If {
+        block: &'a CodeBlock,
+        then: Vec<Statement<'a>>,
+        otherwise: Option<Vec<Statement<'a>>>,
+    },
+    /// `match` or `select!`.
+    Match {
+        block: &'a CodeBlock,
+        arms: Vec<Arm<'a>>,
+    },
+    /// `join!`: every arm runs at the same time.
+    Parallel {
+        block: &'a CodeBlock,
+        arms: Vec<Vec<Statement<'a>>>,
+    },
+    Loop {
+        block: &'a CodeBlock,
+        /// Loop header without the `while ` keyword; `None` for `loop`.
+        header: Option<&'a str>,
+        /// Counter increment a decomposed counted loop ends with.
+        step: Option<&'a str>,
+        body: Vec<Statement<'a>>,
+    },
+}
+
+pub struct Arm<'a> {
+    /// Pattern of a `match` arm, or the future a `select!` arm waits for.
+    pub label: &'a str,
+    pub body: Vec<Statement<'a>>,
+}
+
+impl Statement<'_> {
+    /// Whether the flow does not go on after this statement: an exit, or
+    /// a branching every way of which ends with one.
+    pub fn stops(&self) -> bool {
+        match self {
+            Statement::Simple(block) => block.r#type == BlockType::Terminate || is_return(block),
+            Statement::If { then, otherwise: Some(otherwise), .. } => diverges(then) && diverges(otherwise),
+            Statement::Match { arms, .. } => !arms.is_empty() && arms.iter().all(|arm| diverges(&arm.body)),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the flow never falls off the end of `statements`.
+pub fn diverges(statements: &[Statement]) -> bool {
+    statements.last().is_some_and(Statement::stops)
+}
+
+/// Condition of an `if` or subject of a `match` without the keyword.
+pub fn header_text(block: &CodeBlock) -> &str {
+    ["if ", "match "].iter()
+        .find_map(|keyword| block.text.strip_prefix(keyword))
+        .unwrap_or(&block.text)
+}
+
+pub fn is_return(block: &CodeBlock) -> bool {
+    block.r#type == BlockType::End && block.text.starts_with("return")
+}
+
+/// Splits the analysed blocks into functions and nests their statements.
+pub fn functions(blocks: &[CodeBlock]) -> Vec<Function<'_>> {
+    let mut parser = Parser { blocks, index: 0 };
+    let mut functions = Vec::new();
+    while let Some(block) = parser.peek() {
+        let start = (block.r#type == BlockType::Start).then(|| {
+            parser.index += 1;
+            block
+        });
+        let from = parser.index;
+        let body = parser.sequence();
+        if parser.peek().is_some_and(|b| start.is_some() && function_end(b)) {
+            parser.index += 1;
+        }
+        if parser.index == from {
+            // лишний маркер вне своей конструкции
+            parser.index += 1;
+        }
+        if start.is_some() || !body.is_empty() {
+            functions.push(Function { start, body });
+        }
+    }
+    functions
+}
+
+struct Parser<'a> {
+    blocks: &'a [CodeBlock],
+    index: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a CodeBlock> {
+        self.blocks.get(self.index)
+    }
+
+    fn next_if(&mut self, matches: impl FnOnce(&CodeBlock) -> bool) -> Option<&'a CodeBlock> {
+        let block = self.peek().filter(|b| matches(b))?;
+        self.index += 1;
+        Some(block)
+    }
+
+    /// Statements up to the end of the enclosing branch, arm, loop or
+    /// function.
+    fn sequence(&mut self) -> Vec<Statement<'a>> {
+        let mut statements = Vec::new();
+        while let Some(block) = self.peek() {
+            if ends_sequence(block) {
+                break;
+            }
+            self.index += 1;
+            if let Some(statement) = self.statement(block) {
+                statements.push(statement);
+            }
+        }
+        statements
+    }
+
+    fn statement(&mut self, block: &'a CodeBlock) -> Option<Statement<'a>> {
+        let statement = match block.r#type {
+            BlockType::Condition => {
+                let then = self.sequence();
+                let otherwise = self.next_if(|b| b.r#type == BlockType::Else).map(|_| self.sequence());
+                self.next_if(|b| b.r#type == BlockType::End && b.is_marker());
+                Statement::If { block, then, otherwise }
+            }
+            BlockType::Switch => {
+                let mut arms = Vec::new();
+                while let Some(label) = self.arm() {
+                    arms.push(Arm { label, body: self.sequence() });
+                    self.next_if(|b| b.r#type == BlockType::EndMatchArm);
+                }
+                Statement::Match { block, arms }
+            }
+            BlockType::Parallel => {
+                let mut arms = Vec::new();
+                while self.arm().is_some() {
+                    arms.push(self.sequence());
+                    self.next_if(|b| b.r#type == BlockType::EndMatchArm);
+                }
+                Statement::Parallel { block, arms }
+            }
+            BlockType::Cycle | BlockType::LoopStart | BlockType::LoopTest => {
+                let header = match block.r#type {
+                    // "Цикл А: while i < n", or only "Цикл А" for `loop`
+                    BlockType::LoopStart => block.text.split_once(": ").map(|(_, header)| header),
+                    _ if block.text == "loop" => None,
+                    _ => Some(block.text.as_str()),
+                };
+                let header = header.map(|h| h.strip_prefix("while ").unwrap_or(h));
+                let body = self.sequence();
+                let end = self.next_if(|b| b.loop_origin().is_some() || b.r#type == BlockType::LoopEnd);
+                let step = end
+                    .filter(|_| block.r#type == BlockType::LoopTest)
+                    .and_then(CodeBlock::loop_step);
+                Statement::Loop { block, header, step, body }
+            }
+            BlockType::Connector | BlockType::OffPageConnector => return None,
+            _ if block.is_marker() || function_end(block) => return None,
+            _ => Statement::Simple(block),
+        };
+        Some(statement)
+    }
+
+    /// Starts the next arm of a `match`, `select!` or `join!` and returns
+    /// its label: the pattern, or the future a `select!` arm waits for.
+    /// `None` after the last arm, whose end marker is consumed.
+    fn arm(&mut self) -> Option<&'a str> {
+        let block = self.peek()?;
+        match block.r#type {
+            BlockType::End if block.is_marker() => {
+                self.index += 1;
+                None
+            }
+            BlockType::Case => {
+                self.index += 1;
+                Some(&block.text)
+            }
+            BlockType::EndMatchArm => Some(""),
+            _ if ends_sequence(block) => None,
+            _ => Some(""),
+        }
+    }
+}
+
+/// Blocks that close the statement list they stand in.
+fn ends_sequence(block: &CodeBlock) -> bool {
+    match block.r#type {
+        BlockType::Else | BlockType::EndMatchArm | BlockType::Case | BlockType::LoopEnd | BlockType::Start => true,
+        BlockType::End => block.is_marker() || block.loop_origin().is_some() || function_end(block),
+        _ => false,
+    }
+}
+
+/// `End` that closes a function.
+pub fn function_end(block: &CodeBlock) -> bool {
+    block.r#type == BlockType::End && (block.text.is_empty() || block.text == "}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, CountedLoops, LoopStyle};
+    use crate::lang_vec_stuf::{Language, Rust};
+
+    fn analyze(source: &str, config: &Config) -> Vec<CodeBlock> {
+        Rust.analyze_to_vec(source.to_string(), config)
+    }
+
+    #[test]
+    fn functions_are_split_and_nested() {
+        let blocks = analyze(
+            "fn f(x: i32) -> i32 {\n    if x > 0 {\n        go();\n    } else {\n        return 1;\n    }\n    \
+             match x {\n        0 => zero(),\n        _ => {}\n    }\n    x\n}\n\nfn main() {\n    f(1);\n}\n",
+            &Config::default(),
+        );
+        let parsed = functions(&blocks);
+        let names: Vec<_> = parsed.iter().map(|f| f.start.and_then(|s| s.link.as_deref())).collect();
+        assert_eq!(names, [Some("f"), Some("main")]);
+
+        let [Statement::If { then, otherwise: Some(otherwise), .. }, Statement::Match { arms, .. }, Statement::Simple(last)] =
+            parsed[0].body.as_slice()
+        else {
+            panic!("неверная вложенность");
+        };
+        assert_eq!((then.len(), otherwise.len()), (1, 1));
+        assert!(diverges(otherwise) && !diverges(then));
+        let labels: Vec<_> = arms.iter().map(|arm| (arm.label, arm.body.len())).collect();
+        assert_eq!(labels, [("0", 1), ("_", 0)]);
+        assert_eq!(last.text, "x");
+    }
+
+    #[test]
+    fn loops_keep_header_and_step() {
+        let source = "fn f() {\n    for i in 0..n {\n        while ok() {\n            go(i);\n        }\n    }\n}\n";
+        let config = Config { counted_loops: CountedLoops::Decomposed, ..Config::default() };
+        let blocks = analyze(source, &config);
+        let parsed = functions(&blocks);
+        let [Statement::Simple(init), Statement::Loop { header, step, body, .. }] = parsed[0].body.as_slice() else {
+            panic!("неверная вложенность");
+        };
+        assert_eq!((init.text.as_str(), *header, *step), ("i = 0", Some("i < n"), Some("i = i + 1")));
+        assert!(matches!(body.as_slice(), [Statement::Loop { header: Some("ok()"), step: None, .. }]));
+
+        let config = Config { loop_style: LoopStyle::Boundary, ..Config::default() };
+        let blocks = analyze(source, &config);
+        let parsed = functions(&blocks);
+        let [Statement::Loop { header: Some("for i in 0..n"), body, .. }] = parsed[0].body.as_slice() else {
+            panic!("неверная вложенность");
+        };
+        assert_eq!(body.len(), 1);
+    }
+
+    #[test]
+    fn match_diverges_only_when_every_arm_does() {
+        let blocks = analyze(
+            "fn f(x: i32) {\n    match x {\n        0 => return,\n        _ => panic!(\"x\"),\n    }\n}\n\n\
+             fn g(x: i32) {\n    match x {\n        0 => return,\n        _ => go(),\n    }\n}\n",
+            &Config::default(),
+        );
+        let parsed = functions(&blocks);
+        assert!(diverges(&parsed[0].body));
+        assert!(!diverges(&parsed[1].body));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::config::Background;
+use crate::geometry::{arrowhead, bounds, caption_lines, label_line, outline, route, Anchor, Figure, TextLine};
+use crate::mk_json_blocks::{cross_links, FullJson, Node};
+use crate::sizing::FONT_SIZE;
+
+/// Free space around the drawing.
+const MARGIN: i32 = 20;
+pub(crate) const FONT_FAMILY: &str = "Arial, 'Liberation Sans', 'DejaVu Sans', sans-serif";
+
+/// Draws a diagram as a standalone SVG image: GOST 19.701 shapes with
+/// their text, arrows along orthogonal waypoints and branch labels. Calls
+/// of drawn functions link to the start of the callee.
+pub fn render_svg(diagram: &FullJson, background: Background) -> String {
+    let routes: Vec<Vec<Node>> = diagram.arrows.iter().map(|a| route(diagram, a)).collect();
+    let (left, top, right, bottom) = bounds(diagram.blocks.iter(), routes.iter());
+    let (x, y) = (left - MARGIN, top - MARGIN);
+    let (width, height) = (right - left + 2 * MARGIN, bottom - top + 2 * MARGIN);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"{x} {y} {width} {height}\" font-family=\"{FONT_FAMILY}\" font-size=\"{FONT_SIZE}\">\n"
+    );
+    if background == Background::White {
+        svg.push_str(&format!(
+            "<rect x=\"{x}\" y=\"{y}\" width=\"{width}\" height=\"{height}\" fill=\"white\"/>\n"
+        ));
+    }
+
+    let links = cross_links(diagram);
+    svg.push_str("<g fill=\"white\" stroke=\"black\" stroke-width=\"1.5\">\n");
+    for (index, block) in diagram.blocks.iter().enumerate() {
+        let is_target = links.iter().any(|&(_, target)| target == index);
+        if is_target {
+            svg.push_str(&format!("<g id=\"{}\">\n", anchor(diagram, index)));
+        }
+        for figure in outline(block) {
+            svg.push_str(&figure_element(&figure));
+        }
+        if is_target {
+            svg.push_str("</g>\n");
+        }
+    }
+    svg.push_str("</g>\n<g fill=\"none\" stroke=\"black\" stroke-width=\"1.5\">\n");
+    for (arrow, points) in diagram.arrows.iter().zip(&routes) {
+        let points = points.iter()
+            .map(|p| format!("{},{}", p.x, p.y))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let dash = if arrow.dashed { " stroke-dasharray=\"6 4\"" } else { "" };
+        svg.push_str(&format!("<polyline points=\"{points}\"{dash}/>\n"));
+    }
+    svg.push_str("</g>\n<g fill=\"black\">\n");
+    for (arrow, points) in diagram.arrows.iter().zip(&routes) {
+        if let Some(head) = arrowhead(points).filter(|_| !arrow.dashed) {
+            svg.push_str(&format!("<polygon points=\"{}\"/>\n", point_list(&head)));
+        }
+    }
+    for block in &diagram.blocks {
+        for line in caption_lines(block) {
+            svg.push_str(&text_element(&line));
+        }
+    }
+    for (arrow, points) in diagram.arrows.iter().zip(&routes) {
+        if let Some(line) = label_line(arrow, points) {
+            svg.push_str(&text_element(&line));
+        }
+    }
+    svg.push_str("</g>\n");
+    // прозрачная область поверх блока вызова, чтобы по нему можно было перейти
+    for &(call, target) in &links {
+        let block = &diagram.blocks[call];
+        svg.push_str(&format!(
+            "<a href=\"#{}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\" fill-opacity=\"0\"/></a>\n",
+            anchor(diagram, target),
+            block.x - block.width / 2,
+            block.y - block.height / 2,
+            block.width,
+            block.height
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Fragment identifier of the start block of a function.
+fn anchor(diagram: &FullJson, start: usize) -> String {
+    format!("fn-{}", escape(diagram.blocks[start].link.as_deref().unwrap_or_default()))
+}
+
+fn point_list(points: &[(f32, f32)]) -> String {
+    points.iter()
+        .map(|(x, y)| format!("{x},{y}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn figure_element(figure: &Figure) -> String {
+    let points = point_list(&figure.points);
+    if figure.closed {
+        format!("<polygon points=\"{points}\"/>\n")
+    } else {
+        format!("<polyline points=\"{points}\" fill=\"none\"/>\n")
+    }
+}
+
+fn text_element(line: &TextLine) -> String {
+    let anchor = match line.anchor {
+        Anchor::Start => "start",
+        Anchor::Middle => "middle",
+        Anchor::End => "end",
+    };
+    format!(
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"{anchor}\">{}</text>\n",
+        line.x,
+        line.y,
+        escape(line.text)
+    )
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
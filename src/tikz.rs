@@ -0,0 +1,175 @@
+use crate::config::Config;
+use crate::geometry::{label_line, outline, route, Anchor, Figure};
+use crate::mk_json_blocks::{FullJson, JsBlock, Node};
+use crate::sizing::{FONT_SIZE, LINE_HEIGHT, PADDING};
+
+/// Points per diagram unit (1/96 in).
+const PT_PER_PX: f32 = 0.75;
+
+/// One style per GOST block type, so the whole document can restyle the
+/// diagram in one place.
+const STYLES: &str = "    gost/.style={draw, line width=1.125pt, fill=white},
+    gost process/.style={gost},
+    gost decision/.style={gost},
+    gost data/.style={gost},
+    gost terminator/.style={gost},
+    gost loop/.style={gost},
+    gost preparation/.style={gost},
+    gost predefined/.style={gost},
+    gost loop start/.style={gost},
+    gost loop end/.style={gost},
+    gost connector/.style={gost},
+    gost off-page/.style={gost},
+    gost comment/.style={gost, fill=none},
+    gost arrow/.style={draw, line width=1.125pt, -latex},
+    gost comment line/.style={draw, line width=1.125pt, dash pattern=on 4.5pt off 3pt},
+";
+
+/// Writes a diagram as a TikZ picture with the computed layout: block
+/// outlines, arrows along their waypoints and text, in diagram units
+/// scaled to print at the size of the SVG. With `--tikz-standalone` the
+/// picture is wrapped in a `standalone` document for pdfLaTeX; otherwise
+/// it is meant for `\input` into a document that loads TikZ and a Cyrillic
+/// font encoding.
+pub fn render_tikz(diagram: &FullJson, config: &Config) -> String {
+    let mut tikz = String::new();
+    if config.tikz_standalone {
+        tikz.push_str(&format!(
+            "\\documentclass[tikz, border={}mm]{{standalone}}\n\
+             \\usepackage[T2A]{{fontenc}}\n\
+             \\usepackage[utf8]{{inputenc}}\n\
+             \\begin{{document}}\n",
+            config.margin
+        ));
+    }
+    tikz.push_str(&format!("\\begin{{tikzpicture}}[x={PT_PER_PX}pt, y=-{PT_PER_PX}pt,\n{STYLES}"));
+    tikz.push_str(&format!(
+        "    gost text/.style={{font=\\fontsize{{{}pt}}{{{}pt}}\\selectfont, align=center, inner sep=0pt}},\n    \
+         gost label/.style={{gost text}},\n]\n",
+        FONT_SIZE as f32 * PT_PER_PX,
+        LINE_HEIGHT * PT_PER_PX
+    ));
+
+    for block in &diagram.blocks {
+        let style = style(block);
+        let (w, h) = (block.width, block.height);
+        let (left, top) = (block.x - w / 2, block.y - h / 2);
+        match block.r#type.as_str() {
+            "Начало / конец" => tikz.push_str(&format!(
+                "\\draw[{style}, rounded corners={}pt] ({left},{top}) rectangle ({},{});\n",
+                h as f32 / 2.0 * PT_PER_PX,
+                left + w,
+                top + h
+            )),
+            "Соединитель" => tikz.push_str(&format!(
+                "\\draw[{style}] ({},{}) circle[radius={}];\n",
+                block.x,
+                block.y,
+                w.min(h) as f32 / 2.0
+            )),
+            _ => {
+                for figure in outline(block) {
+                    tikz.push_str(&format!("\\draw[{style}] {};\n", path(&figure)));
+                }
+            }
+        }
+    }
+
+    for arrow in &diagram.arrows {
+        let points = route(diagram, arrow);
+        if points.len() < 2 {
+            continue;
+        }
+        let style = if arrow.dashed { "gost comment line" } else { "gost arrow" };
+        let path = points.iter()
+            .map(|Node { x, y }| format!("({x},{y})"))
+            .collect::<Vec<_>>()
+            .join(" -- ");
+        tikz.push_str(&format!("\\draw[{style}] {path};\n"));
+        if let Some(line) = label_line(arrow, &points) {
+            let anchor = match line.anchor {
+                Anchor::Start => "base west",
+                Anchor::Middle => "base",
+                Anchor::End => "base east",
+            };
+            tikz.push_str(&format!(
+                "\\node[gost label, anchor={anchor}] at ({},{}) {{{}}};\n",
+                line.x,
+                line.y,
+                escape(line.text)
+            ));
+        }
+    }
+
+    for block in diagram.blocks.iter().filter(|b| !b.text.is_empty()) {
+        let text = block.text.lines().map(escape).collect::<Vec<_>>().join("\\\\");
+        if block.text_align == "left" {
+            let left = (block.x - block.width / 2) as f32 + PADDING;
+            tikz.push_str(&format!(
+                "\\node[gost text, anchor=west, align=left] at ({left},{}) {{{text}}};\n",
+                block.y
+            ));
+        } else {
+            tikz.push_str(&format!("\\node[gost text] at ({},{}) {{{text}}};\n", block.x, block.y));
+        }
+    }
+
+    tikz.push_str("\\end{tikzpicture}\n");
+    if config.tikz_standalone {
+        tikz.push_str("\\end{document}\n");
+    }
+    tikz
+}
+
+fn style(block: &JsBlock) -> &'static str {
+    match block.r#type.as_str() {
+        "Условие" => "gost decision",
+        "Ввод / вывод" => "gost data",
+        "Начало / конец" => "gost terminator",
+        "Цикл for" => "gost loop",
+        "Подготовка" => "gost preparation",
+        "Предопределённый процесс" => "gost predefined",
+        "Начало цикла" => "gost loop start",
+        "Конец цикла" => "gost loop end",
+        "Соединитель" => "gost connector",
+        "Межстраничный соединитель" => "gost off-page",
+        "Комментарий" => "gost comment",
+        _ => "gost process",
+    }
+}
+
+fn path(figure: &Figure) -> String {
+    let mut path = figure.points.iter()
+        .map(|(x, y)| format!("({x},{y})"))
+        .collect::<Vec<_>>()
+        .join(" -- ");
+    if figure.closed {
+        path.push_str(" -- cycle");
+    }
+    path
+}
+
+/// Text of the source code set literally: TeX special characters become
+/// commands, and `--` is split so it does not turn into a dash.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut previous = None;
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '{' | '}' | '&' | '%' | '$' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '<' => escaped.push_str("\\textless{}"),
+            '>' => escaped.push_str("\\textgreater{}"),
+            '|' => escaped.push_str("\\textbar{}"),
+            '-' if previous == Some('-') => escaped.push_str("{}-"),
+            _ => escaped.push(c),
+        }
+        previous = Some(c);
+    }
+    escaped
+}
@@ -0,0 +1,200 @@
+/// Longest caption kept inside a block; longer ones are cut with an
+/// ellipsis and written out in full in an attached comment.
+pub const MAX_LINES: usize = 4;
+
+const ELLIPSIS: char = '…';
+
+/// Multi-character operators that are never split.
+const OPERATORS: [&str; 19] = [
+    "..=", "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "%=",
+    "<<", ">>", "..", "?.",
+];
+
+#[derive(PartialEq)]
+enum Token<'a> {
+    Space,
+    /// Identifier, number or string literal: never split.
+    Word(&'a str),
+    /// Operator or punctuation: lines may break after it.
+    Operator(&'a str),
+}
+
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let length = if c.is_whitespace() {
+            let end = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+            tokens.push(Token::Space);
+            rest = &rest[end..];
+            continue;
+        } else if c == '"' {
+            string_literal_length(rest)
+        } else if let Some(length) = char_literal_length(rest) {
+            length
+        } else if c.is_alphanumeric() || c == '_' {
+            rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len())
+        } else {
+            let operator = OPERATORS.iter().find(|op| rest.starts_with(**op));
+            let length = operator.map_or(c.len_utf8(), |op| op.len());
+            tokens.push(Token::Operator(&rest[..length]));
+            rest = &rest[length..];
+            continue;
+        };
+        // r"..", b'.' and friends stay glued to their literal
+        match tokens.last_mut() {
+            Some(Token::Word(previous)) if c == '"' || c == '\'' => {
+                let start = text.len() - rest.len() - previous.len();
+                *previous = &text[start..start + previous.len() + length];
+            }
+            _ => tokens.push(Token::Word(&rest[..length])),
+        }
+        rest = &rest[length..];
+    }
+    tokens
+}
+
+/// Byte length of the string literal `text` starts with, escapes included.
+fn string_literal_length(text: &str) -> usize {
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return index + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Byte length of the character literal `text` starts with; `None` for a
+/// lifetime or loop label.
+fn char_literal_length(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1);
+    let (_, first) = chars.next()?;
+    let (end, _) = if first == '\\' {
+        // '\n', '\'', '\u{7f}'
+        chars.skip(1).find(|&(_, c)| c == '\'')?
+    } else {
+        chars.next().filter(|&(_, c)| c == '\'')?
+    };
+    Some(end + 1)
+}
+
+/// Whether a line may not break right before `token`: closing brackets
+/// and the punctuation that ends an expression stay with what they
+/// close, and `.` and `::` stay with the names on both sides.
+fn is_glued(token: &Token) -> bool {
+    matches!(token, Token::Operator(")" | "]" | "}" | "," | ";" | "?")) || is_path_separator(token)
+}
+
+fn is_path_separator(token: &Token) -> bool {
+    matches!(token, Token::Operator("." | "::"))
+}
+
+/// Greedily wraps `text` to lines of at most `width` characters, breaking
+/// at whitespace and after operators. Identifiers, literals, paths,
+/// `.method` and empty brackets are never split, so a single long one may
+/// exceed `width`. Existing line breaks are kept.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        // byte offset of the last place in `line` it may break at
+        let mut last_break = None;
+        let mut breakable = false;
+        let mut space = false;
+
+        for token in tokenize(paragraph) {
+            let piece = match token {
+                Token::Space => {
+                    space = !line.is_empty();
+                    breakable = true;
+                    continue;
+                }
+                Token::Word(piece) | Token::Operator(piece) => piece,
+            };
+            if breakable && !is_glued(&token) && !line.is_empty() {
+                last_break = Some(line.len());
+            }
+            if space {
+                line.push(' ');
+            }
+            line.push_str(piece);
+            space = false;
+            breakable = matches!(token, Token::Operator(_)) && !is_path_separator(&token);
+
+            if line.chars().count() > width {
+                if let Some(at) = last_break.take() {
+                    let rest = line.split_off(at);
+                    lines.push(std::mem::replace(&mut line, rest.trim_start().to_string()));
+                }
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Longest line allowed for an unbreakable identifier or literal.
+fn max_line(width: usize) -> usize {
+    width + width / 2
+}
+
+/// `text` wrapped to `width` and cut to `MAX_LINES`; cut lines and
+/// unbreakable lines much wider than `width` end with an ellipsis.
+pub fn caption_text(text: &str, width: usize) -> String {
+    let mut lines = wrap_text(text, width);
+    for line in &mut lines {
+        if line.chars().count() > max_line(width) {
+            *line = line.chars().take(width).collect();
+            line.push(ELLIPSIS);
+        }
+    }
+    if lines.len() > MAX_LINES {
+        lines.truncate(MAX_LINES);
+        if let Some(last) = lines.last_mut() {
+            if !last.ends_with(ELLIPSIS) {
+                last.push(ELLIPSIS);
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+/// Whether `text` is too long to be written in full inside a block.
+pub fn is_overlong(text: &str, width: usize) -> bool {
+    let lines = wrap_text(text, width);
+    lines.len() > MAX_LINES || lines.iter().any(|line| line.chars().count() > max_line(width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_and_methods_are_not_split() {
+        assert_eq!(wrap_text("let names = String::new()", 24), ["let names =", "String::new()"]);
+        assert_eq!(wrap_text("let data = fetch().await", 19), ["let data =", "fetch().await"]);
+        assert_eq!(wrap_text("value.method_name", 6), ["value.method_name"]);
+        assert_eq!(wrap_text("total(a, b)", 8), ["total(a,", "b)"]);
+    }
+
+    #[test]
+    fn quote_char_is_not_a_string() {
+        let tokens = tokenize("c == '\"' || c == 'x'");
+        assert!(tokens.contains(&Token::Word("'\"'")));
+        assert!(tokens.contains(&Token::Word("'x'")));
+        assert_eq!(wrap_text("if c == '\"' && ok", 12), ["if c == '\"'", "&& ok"]);
+    }
+
+    #[test]
+    fn labels_and_escapes_are_tokenized() {
+        assert!(tokenize("break 'outer").contains(&Token::Operator("'")));
+        assert!(tokenize("c != '\\''").contains(&Token::Word("'\\''")));
+        assert!(tokenize("b'a'").contains(&Token::Word("b'a'")));
+    }
+}